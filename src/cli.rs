@@ -0,0 +1,278 @@
+//! Command line options for the emulator binary
+
+use std::path::PathBuf;
+
+use rbgb::{Model, Palette, VideoFormat};
+
+pub const HELP: &str = "\
+Usage: rbgb [OPTIONS] [ROM]
+
+Arguments:
+  [ROM]                Game to load on startup, otherwise press L in the window
+
+Options:
+  --scale <N>          Window size as a multiple of 160x144 [default: the last size]
+  --fullscreen         Start in fullscreen
+  --palette <NAME>     DMG palette: grayscale, green, pocket or light
+  --model <MODEL>      Hardware to emulate: dmg0, dmg, mgb, sgb, sgb2 or cgb [default: dmg]
+  --boot-rom <PATH>    Boot ROM to run before the game
+  --save-dir <DIR>     Where to write movies and screenshots, defaults to next to the ROM
+  --speed <N>          Emulation speed, 1 is real time [default: 1]
+  --start-paused       Load the ROM paused
+  --headless           Run without a window, needs a ROM
+  --frames <N>         Stop after this many frames when headless
+  --screenshot-frame <N>
+                       Save frame N as a PNG when headless, then stop unless --frames is given
+  --record <PATH>      Record video from the start, as .gif, .png (APNG) or .y4m with a .wav,
+                       needs --frames when headless
+  --video-format <FORMAT>
+                       Format for videos started with F9: gif, apng or y4m [default: gif]
+  --printer            Plug a Game Boy Printer into the link port, prints are saved as PNGs
+  -h, --help           Print this help
+";
+
+// Options that have no effect without a window
+const WINDOW_OPTIONS: [&str; 5] = [
+    "--scale",
+    "--fullscreen",
+    "--speed",
+    "--start-paused",
+    "--video-format",
+];
+
+pub struct Options {
+    pub rom: Option<PathBuf>,
+    pub scale: Option<u32>,
+    pub fullscreen: bool,
+    pub palette: Option<Palette>,
+    pub model: Model,
+    pub boot_rom: Option<PathBuf>,
+    pub save_dir: Option<PathBuf>,
+    pub speed: f32,
+    pub start_paused: bool,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub screenshot_frame: Option<u64>,
+    pub record: Option<(PathBuf, VideoFormat)>,
+    pub video_format: VideoFormat,
+    pub printer: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rom: None,
+            scale: None,
+            fullscreen: false,
+            palette: None,
+            model: Model::Dmg,
+            boot_rom: None,
+            save_dir: None,
+            speed: 1.0,
+            start_paused: false,
+            headless: false,
+            frames: None,
+            screenshot_frame: None,
+            record: None,
+            video_format: VideoFormat::default(),
+            printer: false,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments after the program name
+    ///
+    /// Values can follow their option as the next argument or after an `=`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        // The first option given that only means something with a window
+        let mut window_option = None;
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                if options.rom.is_some() {
+                    return Err(format!("Unexpected argument '{arg}'"));
+                }
+                options.rom = Some(PathBuf::from(arg));
+                continue;
+            }
+
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{name} needs a value"))
+            };
+            let flag = || match inline {
+                Some(_) => Err(format!("{name} doesn't take a value")),
+                None => Ok(true),
+            };
+            let invalid = |value: &str| format!("Invalid value '{value}' for {name}");
+
+            match name {
+                "--scale" => {
+                    let scale = value()?;
+                    options.scale = Some(
+                        scale
+                            .parse()
+                            .ok()
+                            .filter(|scale| (1..=16).contains(scale))
+                            .ok_or_else(|| invalid(&scale))?,
+                    );
+                }
+                "--palette" => {
+                    let palette = value()?;
+                    options.palette =
+                        Some(Palette::from_name(&palette).ok_or_else(|| invalid(&palette))?);
+                }
+                "--model" => {
+                    let model = value()?;
+                    options.model = Model::from_name(&model).ok_or_else(|| invalid(&model))?;
+                }
+                "--speed" => {
+                    let speed = value()?;
+                    options.speed = speed
+                        .parse()
+                        .ok()
+                        .filter(|speed: &f32| *speed > 0.0 && speed.is_finite())
+                        .ok_or_else(|| invalid(&speed))?;
+                }
+                "--frames" => {
+                    let frames = value()?;
+                    options.frames = Some(frames.parse().map_err(|_| invalid(&frames))?);
+                }
+                "--screenshot-frame" => {
+                    let frame = value()?;
+                    // Frames are counted from 1, so there's no frame 0 to save
+                    options.screenshot_frame = Some(
+                        frame
+                            .parse()
+                            .ok()
+                            .filter(|frame: &u64| *frame > 0)
+                            .ok_or_else(|| invalid(&frame))?,
+                    );
+                }
+                "--record" => {
+                    let path = value()?;
+                    let format = VideoFormat::from_path(&path).ok_or_else(|| invalid(&path))?;
+                    options.record = Some((PathBuf::from(path), format));
+                }
+                "--video-format" => {
+                    let format = value()?;
+                    options.video_format =
+                        VideoFormat::from_name(&format).ok_or_else(|| invalid(&format))?;
+                }
+                "--boot-rom" => options.boot_rom = Some(PathBuf::from(value()?)),
+                "--save-dir" => options.save_dir = Some(PathBuf::from(value()?)),
+                "--fullscreen" => options.fullscreen = flag()?,
+                "--start-paused" => options.start_paused = flag()?,
+                "--headless" => options.headless = flag()?,
+                "--printer" => options.printer = flag()?,
+                "-h" | "--help" => options.help = flag()?,
+                _ => return Err(format!("Unknown option '{name}'")),
+            }
+            if WINDOW_OPTIONS.contains(&name) && window_option.is_none() {
+                window_option = Some(name.to_string());
+            }
+        }
+
+        if options.headless && options.rom.is_none() && !options.help {
+            return Err("--headless needs a ROM".to_string());
+        }
+        if let Some(name) = window_option.filter(|_| options.headless) {
+            return Err(format!("{name} can't be used with --headless"));
+        }
+        // Headless runs only stop on their own after a set number of frames
+        if options.headless
+            && options.record.is_some()
+            && options.frames.is_none()
+            && options.screenshot_frame.is_none()
+        {
+            return Err("--record needs --frames when headless".to_string());
+        }
+        if let (Some(frames), Some(frame)) = (options.frames, options.screenshot_frame)
+            && frames < frame
+        {
+            return Err(format!(
+                "--screenshot-frame {frame} is after the last of --frames {frames}"
+            ));
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_parse_options() {
+        let options = parse(&["--scale", "3", "game.gb", "--model=cgb"]).unwrap();
+        assert_eq!(options.rom, Some(PathBuf::from("game.gb")));
+        assert_eq!(options.scale, Some(3));
+        assert_eq!(options.model, Model::Cgb);
+        assert!(!options.headless);
+
+        let options = parse(&["--palette", "Pocket", "--speed", "2.5"]).unwrap();
+        assert_eq!(options.palette, Some(Palette::POCKET));
+        assert_eq!(options.speed, 2.5);
+
+        let options = parse(&["--record", "run.y4m", "--video-format=APNG", "--printer"]).unwrap();
+        assert_eq!(
+            options.record,
+            Some((PathBuf::from("run.y4m"), VideoFormat::Y4m))
+        );
+        assert_eq!(options.video_format, VideoFormat::Apng);
+        assert!(options.printer);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_bad_options() {
+        assert!(parse(&["--scale"]).is_err());
+        assert!(parse(&["--scale", "0"]).is_err());
+        assert!(parse(&["--model", "gba"]).is_err());
+        assert!(parse(&["--fullscreen=yes"]).is_err());
+        assert!(parse(&["--mute"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["a.gb", "b.gb"]).is_err());
+        assert!(parse(&["--headless"]).is_err());
+        assert!(parse(&["--headless", "a.gb", "--frames", "10"]).is_ok());
+        assert!(parse(&["--headless", "a.gb", "--scale", "2"]).is_err());
+        assert!(parse(&["--fullscreen", "--headless", "a.gb"]).is_err());
+        assert!(parse(&["--headless", "a.gb", "--speed=2"]).is_err());
+        assert!(parse(&["--headless", "a.gb", "--start-paused"]).is_err());
+        assert!(parse(&["--headless", "a.gb", "--record", "run.gif"]).is_err());
+        assert!(
+            parse(&[
+                "--headless",
+                "a.gb",
+                "--record",
+                "run.gif",
+                "--frames",
+                "60"
+            ])
+            .is_ok()
+        );
+        assert!(parse(&["a.gb", "--record", "run.gif"]).is_ok());
+        assert!(parse(&["--headless", "a.gb", "--video-format", "y4m"]).is_err());
+        assert!(parse(&["--screenshot-frame", "0"]).is_err());
+        assert!(parse(&["--frames", "10", "--screenshot-frame", "11"]).is_err());
+        assert!(parse(&["--frames", "10", "--screenshot-frame", "10"]).is_ok());
+        assert!(parse(&["--record", "run.mp4"]).is_err());
+    }
+}
@@ -1,10 +1,43 @@
 use crate::types::{GameInput, KeyState};
 
+mod cheats;
+mod checksum;
+mod config;
 mod cpu;
 mod graphics;
 mod joypad;
 mod mem;
+mod model;
+#[cfg(feature = "std")]
+mod movie;
+mod palette;
+#[cfg(feature = "std")]
+mod png;
+mod printer;
+#[cfg(feature = "std")]
+mod recording;
+mod run;
+mod serial;
 mod sound;
+mod timer;
+
+pub use cheats::{Cheat, CheatCode, Cheats, MAX_CHEATS};
+pub use config::{
+    Config, EmulatorBuilder, MemoryAccess, PixelFormat, Trace, TraceCallback, TraceEvent,
+};
+pub use mem::MemoryRegion;
+pub use model::Model;
+#[cfg(feature = "std")]
+pub use movie::Movie;
+pub use palette::{Palette, Shades};
+#[cfg(feature = "std")]
+pub use png::{encode_png, timestamped_name};
+#[cfg(feature = "std")]
+pub use printer::Paper;
+pub use printer::Print;
+#[cfg(feature = "std")]
+pub use recording::{Recorder, VideoFormat};
+pub use run::{RunResult, StopReason};
 
 /// High-level Game Boy emulator coordinator.
 ///
@@ -15,10 +48,32 @@ mod sound;
 /// This type exposes the core API used by the frontend to load ROMs, advance
 /// frames, provide input, and read the display buffer.
 pub struct Emulator {
-    screen: graphics::Screen,
     cpu: cpu::CPU,
     joypad: joypad::Joypad,
     paused: bool,
+    config: Config,
+    /// Frames finished since the emulator was created
+    frames: u64,
+    /// Cycles run since the last frame was finished
+    frame_cycles: u32,
+    #[cfg(feature = "std")]
+    movie: MovieState,
+    /// Set between frames, so the movie input is handled once the next frame
+    /// starts and after any input given in between
+    #[cfg(feature = "std")]
+    frame_starting: bool,
+}
+
+#[cfg(feature = "std")]
+/// Whether input is being recorded or played back
+enum MovieState {
+    Idle,
+    Recording(Movie),
+    /// Playing back a movie, `frame` is the next frame to start
+    Playing {
+        movie: Movie,
+        frame: usize,
+    },
 }
 
 impl Default for Emulator {
@@ -28,11 +83,11 @@ impl Default for Emulator {
 }
 
 impl Emulator {
-    /// Maximum CPU cycles executed per frame.
+    /// Cycles in one video frame, 154 lines of 456 cycles.
     ///
-    /// This matches the approximate number of cycles a Game Boy runs in one
-    /// video frame. The update loop runs until this budget is reached.
-    const MAXCYCLES: u32 = 69905;
+    /// Frames are timed by the PPU, this is only used to keep frames coming
+    /// while the LCD is off or the system is stopped.
+    pub const FRAME_CYCLES: u32 = 70224;
 
     /// Create a new emulator instance with initialized subsystems.
     ///
@@ -41,37 +96,198 @@ impl Emulator {
     ///
     /// Returns a ready-to-use `Emulator` in the paused state.
     pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    /// Create a new emulator that runs as the given hardware model.
+    ///
+    /// With [`Model::Cgb`] cartridges that support the Game Boy Color get its
+    /// color palettes, extra VRAM/WRAM banks, VRAM DMA and double speed mode,
+    /// while older cartridges run in the DMG compatibility mode.
+    pub fn with_model(model: Model) -> Self {
+        Self::with_config(Config {
+            model,
+            ..Config::default()
+        })
+    }
+
+    /// Create a new emulator with the given settings.
+    ///
+    /// See [`EmulatorBuilder`] for a more convenient way to fill in a config.
+    /// The trace callback is handed over to the CPU, so the config kept by the
+    /// emulator no longer has one.
+    pub fn with_config(mut config: Config) -> Self {
         let mut cpu = cpu::CPU::new();
+        cpu.set_memory_access(config.memory_access);
+        cpu.set_trace(Trace {
+            callback: config.trace.callback.take(),
+            ..config.trace
+        });
+        cpu.screen_mut().set_palette(config.palette);
+        cpu.screen_mut().set_pixel_format(config.pixel_format);
+        cpu.memory_mut().set_model(config.model);
         cpu.memory_mut().ram_startup();
         Emulator {
-            screen: graphics::Screen::new(),
             cpu,
             joypad: joypad::Joypad::new(),
             paused: true,
+            config,
+            frames: 0,
+            frame_cycles: 0,
+            #[cfg(feature = "std")]
+            movie: MovieState::Idle,
+            #[cfg(feature = "std")]
+            frame_starting: true,
         }
     }
 
+    /// The settings the emulator was created with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The hardware model being emulated.
+    pub fn model(&self) -> Model {
+        self.cpu.memory().model()
+    }
+
     /// Execute one frame of emulation if not paused.
     ///
-    /// Runs CPU instructions, updates timers and graphics, and handles
-    /// interrupts until the frame's cycle budget is consumed.
+    /// Runs CPU instructions until the PPU enters VBlank, so the display buffer
+    /// holds a whole frame when this returns. The instruction that was running
+    /// at the time is finished, and the cycles it took past VBlank count
+    /// towards the next frame. Timers, graphics, DMA and interrupts are stepped
+    /// by the CPU as each instruction accesses memory.
     ///
-    /// Returns `()` and has no effect if the emulator is paused.
-    pub fn update(&mut self) {
+    /// While the LCD is off or the system is stopped a blank frame is produced
+    /// every [`Self::FRAME_CYCLES`] cycles instead.
+    ///
+    /// Returns `true` if a new frame was produced, which is always the case
+    /// unless the emulator is paused.
+    pub fn update(&mut self) -> bool {
         if self.paused {
-            return;
+            return false;
+        }
+        while !self.step().1 {}
+        true
+    }
+
+    /// Execute a single instruction, even while paused.
+    ///
+    /// An interrupt dispatch counts as an instruction, as does one cycle of
+    /// HALT or STOP spent waiting.
+    ///
+    /// Returns the cycles the instruction took.
+    pub fn step_instruction(&mut self) -> u32 {
+        self.step().0
+    }
+
+    /// Execute instructions for at least `cycles` cycles, even while paused.
+    ///
+    /// Returns how many cycles actually ran and how many frames were finished.
+    pub fn run_cycles(&mut self, cycles: u64) -> RunResult {
+        self.run_until(cycles, |_| false)
+    }
+
+    /// Execute instructions until `predicate` returns `true`, even while paused.
+    ///
+    /// The predicate is checked after every instruction. The run gives up once
+    /// `max_cycles` cycles have passed, so a condition that never comes true
+    /// can't hang the caller.
+    ///
+    /// Returns how many cycles ran, how many frames were finished and whether
+    /// the predicate or the cycle limit stopped the run.
+    pub fn run_until<F>(&mut self, max_cycles: u64, mut predicate: F) -> RunResult
+    where
+        F: FnMut(&Emulator) -> bool,
+    {
+        let mut result = RunResult {
+            cycles: 0,
+            frames: 0,
+            reason: StopReason::Cycles,
+        };
+        while result.cycles < max_cycles {
+            let (cycles, frame) = self.step();
+            result.cycles += cycles as u64;
+            result.frames += frame as u32;
+            if predicate(self) {
+                result.reason = StopReason::Predicate;
+                break;
+            }
+        }
+        result
+    }
+
+    /// Address of the next instruction the CPU will execute.
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    /// Frames produced since the emulator was created.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Runs a single instruction and keeps count of frames.
+    ///
+    /// Returns the cycles it took and `true` if it finished a frame.
+    fn step(&mut self) -> (u32, bool) {
+        #[cfg(feature = "std")]
+        if self.frame_starting {
+            self.frame_starting = false;
+            self.advance_movie();
+        }
+
+        let cycles = self.cpu.execute_next_opcode(false) as u32;
+        self.frame_cycles += cycles;
+
+        let frame = if self.cpu.screen_mut().take_frame() {
+            self.frame_cycles = 0;
+            true
+        } else if self.frame_cycles >= Self::FRAME_CYCLES && !self.ppu_running() {
+            self.frame_cycles -= Self::FRAME_CYCLES;
+            self.cpu.screen_mut().blank();
+            true
+        } else {
+            false
+        };
+        if frame {
+            self.frames += 1;
+            self.apply_game_shark();
+            #[cfg(feature = "std")]
+            {
+                self.frame_starting = true;
+            }
         }
-        let mut num_cycles: u32 = 0;
-        while num_cycles < Self::MAXCYCLES {
-            let cycles = self.cpu.execute_next_opcode(false);
-            num_cycles += cycles as u32;
-            self.cpu.update_timers(cycles as i32);
-            self.screen
-                .update_screen(self.cpu.memory_mut(), cycles as i32);
-            self.cpu.handle_interrupts();
+        (cycles, frame)
+    }
+
+    /// Writes the enabled GameShark codes into RAM, done once per frame.
+    fn apply_game_shark(&mut self) {
+        let mem = self.cpu.memory_mut();
+        let cheats = *mem.cheats();
+        for cheat in cheats.iter().filter(|cheat| cheat.enabled) {
+            match cheat.code {
+                CheatCode::GameShark {
+                    bank: Some(bank),
+                    address: address @ 0xD000..0xE000,
+                    value,
+                } => {
+                    let offset = (address - 0xD000) as usize;
+                    let _ = mem.poke_bank(MemoryRegion::Wram, bank as usize, offset, value);
+                }
+                CheatCode::GameShark { address, value, .. } => mem.poke(address, value),
+                CheatCode::GameGenie { .. } => {}
+            }
         }
     }
 
+    /// Whether the PPU is drawing and will finish frames on its own.
+    fn ppu_running(&self) -> bool {
+        let screen = self.cpu.screen();
+        screen.is_lcd_enabled(self.cpu.memory()) && !self.cpu.is_stopped()
+    }
+
     /// Toggle the paused state.
     ///
     /// When paused, `update` returns immediately without advancing emulation.
@@ -91,12 +307,44 @@ impl Emulator {
     }
 
     /// Load ROM data and reset CPU/memory state.
+    ///
+    /// If a boot ROM was set with `set_boot_rom` it runs first, otherwise the
+    /// CPU starts at 0x0100 with the state a boot ROM would have left behind.
+    /// That includes the colors the CGB boot ROM picks for older games when
+    /// running as [`Model::Cgb`].
     pub fn load_rom_data(&mut self, data: &[u8]) {
+        self.cpu.memory_mut().load_rom_data(data);
+        self.power_on();
+    }
+
+    /// Turn the system off and on again with the same ROM.
+    ///
+    /// Everything but the ROM, boot ROM, settings and cheats goes back to its
+    /// power on state, including cartridge RAM. The emulator is unpaused.
+    pub fn reset(&mut self) {
+        self.cpu.memory_mut().restart();
+        self.power_on();
+    }
+
+    /// Starts the freshly cleared memory up the way the boot ROM would.
+    fn power_on(&mut self) {
+        self.cpu.screen_mut().reset();
         let mem = self.cpu.memory_mut();
-        mem.load_rom_data(data);
-        mem.ram_startup();
+        let colorize = mem.model().is_cgb() && !mem.cgb_mode();
+        if !mem.boot_rom_mapped() {
+            mem.ram_startup();
+            if colorize {
+                let palette = self.cgb_compat_palette();
+                self.cpu.screen_mut().set_palette(palette);
+            }
+        }
         self.cpu.reset();
+        self.frame_cycles = 0;
         self.paused = false;
+        #[cfg(feature = "std")]
+        {
+            self.frame_starting = true;
+        }
     }
 
     #[cfg(feature = "std")]
@@ -115,14 +363,214 @@ impl Emulator {
         Ok(())
     }
 
+    /// Set a boot ROM image to run whenever a ROM is loaded.
+    ///
+    /// DMG and MGB images are 256 bytes and CGB images are 2304 bytes. The boot
+    /// ROM is mapped over the start of the cartridge until it writes to 0xFF50,
+    /// so the logo scroll plays and the registers end up wherever the boot ROM
+    /// leaves them. Passing an empty slice goes back to skipping the boot ROM.
+    ///
+    /// Returns an error if the image is not one of the supported sizes.
+    pub fn set_boot_rom(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        self.cpu.memory_mut().set_boot_rom(data)
+    }
+
+    #[cfg(feature = "std")]
+    /// Read a boot ROM image from disk, see `set_boot_rom`.
+    ///
+    /// Parameters:
+    /// - `path`: filesystem path to the boot ROM file.
+    ///
+    /// Returns `Ok(())` on success or a string error on failure.
+    pub fn load_boot_rom(&mut self, path: &str) -> Result<(), std::string::String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        self.set_boot_rom(&data).map_err(|e| e.to_string())
+    }
+
+    /// Change the colors used for DMG graphics.
+    ///
+    /// Takes effect from the next drawn line and lasts until the next ROM
+    /// load on [`Model::Cgb`], where the boot ROM colors are picked again.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.config.palette = palette;
+        self.cpu.screen_mut().set_palette(palette);
+    }
+
+    /// The colors currently used for DMG graphics.
+    pub fn palette(&self) -> Palette {
+        self.cpu.screen().palette()
+    }
+
+    /// The colors the CGB boot ROM would give the loaded game.
+    ///
+    /// Works for any model, so older games can be colored on a DMG too.
+    pub fn cgb_compat_palette(&self) -> Palette {
+        let mem = self.cpu.memory();
+        Palette::cgb_compat(mem.title_checksum(), mem.read_byte_forced(0x137))
+    }
+
+    /// Change the layout of the display buffer.
+    ///
+    /// The PPU writes the new format from the next drawn line, so the buffer
+    /// only holds a complete picture in it once the next frame is done.
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.config.pixel_format = format;
+        self.cpu.screen_mut().set_pixel_format(format);
+    }
+
+    /// The layout of the display buffer.
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.cpu.screen().pixel_format()
+    }
+
     /// Borrow the current display buffer for rendering.
     ///
-    /// The buffer contains raw pixel data produced by the graphics subsystem.
-    /// Its length and format are determined by `graphics::Screen`.
+    /// The buffer contains the pixels produced by the graphics subsystem in
+    /// the configured [`PixelFormat`], 160 * 144 pixels of
+    /// [`PixelFormat::bytes_per_pixel`] bytes each.
     ///
     /// Returns a borrowed `&[u8]` slice tied to the emulator's lifetime.
     pub fn get_display_buffer(&self) -> &[u8] {
-        &self.screen.buffer
+        self.cpu.screen().buffer()
+    }
+
+    #[cfg(feature = "std")]
+    /// Encode the display buffer as a PNG image.
+    ///
+    /// Works with any [`PixelFormat`], with shade indexes saved as grays.
+    pub fn screenshot_png(&self) -> std::vec::Vec<u8> {
+        let rgb = png::to_rgb(self.get_display_buffer(), self.pixel_format());
+        encode_png(crate::SCREEN_WIDTH, crate::SCREEN_HEIGHT, &rgb)
+    }
+
+    #[cfg(feature = "std")]
+    /// Save the display buffer to disk as a PNG image, see `screenshot_png`.
+    ///
+    /// Parameters:
+    /// - `path`: filesystem path to write, [`timestamped_name`] makes a
+    ///   fresh one for each screenshot.
+    ///
+    /// Returns `Ok(())` on success or a string I/O error on failure.
+    pub fn save_screenshot(&self, path: &str) -> Result<(), std::string::String> {
+        std::fs::write(path, self.screenshot_png()).map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "std")]
+    /// Add the display buffer to a recording as its next frame.
+    ///
+    /// Call once for each frame `update` finishes, so the recording plays
+    /// back at the Game Boy's speed whatever speed the emulator runs at.
+    ///
+    /// Returns a string I/O error if the frame couldn't be written.
+    pub fn record_frame(&self, recorder: &mut Recorder) -> Result<(), std::string::String> {
+        recorder.add_frame(&png::to_rgb(self.get_display_buffer(), self.pixel_format()))
+    }
+
+    /// Read a byte the way the CPU would see it right now.
+    ///
+    /// Unlike a read by the CPU this never counts as an access, so it is safe
+    /// to call at any time, and works on VRAM and OAM while the PPU is using
+    /// them.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.memory().read_byte_forced(addr)
+    }
+
+    /// Write a byte wherever the CPU would see it right now.
+    ///
+    /// The write has none of the side effects a CPU write would have: ROM is
+    /// patched instead of switching banks, disabled cartridge RAM is written
+    /// anyway, and register writes don't start DMA or serial transfers, raise
+    /// joypad interrupts or reset DIV.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.cpu.memory_mut().poke(addr, value)
+    }
+
+    /// How many banks a region has.
+    ///
+    /// ROM banks depend on the loaded cartridge, VRAM and WRAM on whether the
+    /// model is a [`Model::Cgb`].
+    pub fn bank_count(&self, region: MemoryRegion) -> usize {
+        self.cpu.memory().bank_count(region)
+    }
+
+    /// Read a byte from any bank of a region, mapped or not.
+    ///
+    /// `offset` is from the start of the bank, see
+    /// [`MemoryRegion::bank_size`].
+    ///
+    /// Returns an error if the bank or offset doesn't exist.
+    pub fn peek_bank(
+        &self,
+        region: MemoryRegion,
+        bank: usize,
+        offset: usize,
+    ) -> Result<u8, &'static str> {
+        self.cpu.memory().peek_bank(region, bank, offset)
+    }
+
+    /// Write a byte to any bank of a region, mapped or not, see `poke`.
+    ///
+    /// Returns an error if the bank or offset doesn't exist.
+    pub fn poke_bank(
+        &mut self,
+        region: MemoryRegion,
+        bank: usize,
+        offset: usize,
+        value: u8,
+    ) -> Result<(), &'static str> {
+        self.cpu.memory_mut().poke_bank(region, bank, offset, value)
+    }
+
+    /// Plug a Game Boy Printer into the link port, or unplug it.
+    ///
+    /// The printer answers games that print, and stays plugged in across
+    /// resets and ROM loads. Its prints are picked up with `take_print`.
+    pub fn connect_printer(&mut self, connected: bool) {
+        self.cpu
+            .memory_mut()
+            .serial_mut()
+            .connect_printer(connected);
+    }
+
+    /// Whether a printer is plugged into the link port.
+    pub fn printer_connected(&self) -> bool {
+        self.cpu.memory().serial().printer_connected()
+    }
+
+    /// Take the image the printer last printed, if there's a new one.
+    ///
+    /// Call at least once a frame, a print that isn't taken is replaced by
+    /// the next one. [`Paper`] joins prints into pages.
+    pub fn take_print(&mut self) -> Option<Print> {
+        self.cpu.memory_mut().serial_mut().take_print()
+    }
+
+    /// The cheats applied to the running game.
+    pub fn cheats(&self) -> &Cheats {
+        self.cpu.memory().cheats()
+    }
+
+    /// Add, remove or toggle cheats.
+    ///
+    /// Game Genie codes patch ROM reads straight away and GameShark codes are
+    /// written into RAM at the end of every frame. Cheats are kept when a new
+    /// ROM is loaded.
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        self.cpu.memory_mut().cheats_mut()
+    }
+
+    #[cfg(feature = "std")]
+    /// Add the codes in a cheat file, see [`Cheats::load`].
+    ///
+    /// Parameters:
+    /// - `path`: filesystem path to the cheat file.
+    ///
+    /// Returns the number of codes added or a string error on failure.
+    pub fn load_cheats(&mut self, path: &str) -> Result<usize, std::string::String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.cheats_mut()
+            .load(&text)
+            .map_err(|(line, e)| std::format!("{path}:{line}: {e}"))
     }
 
     /// Prints our all relevant memory locations into the stdout
@@ -153,7 +601,7 @@ impl Emulator {
     /// Handle input for the emulator's joypad.
     ///
     /// Updates the joypad state and forwards the input to memory-mapped input
-    /// registers.
+    /// registers. Input is ignored while a movie is playing.
     ///
     /// Parameters:
     /// - `input`: which Game Boy control was pressed or released.
@@ -161,6 +609,279 @@ impl Emulator {
     ///
     /// Returns `()`.
     pub fn game_input(&mut self, input: GameInput, val: KeyState) {
+        #[cfg(feature = "std")]
+        if self.is_playing_movie() {
+            return;
+        }
         self.joypad.log_input(self.cpu.memory_mut(), input, val)
     }
+
+    /// Buttons held down, a bit each for A, B, Select, Start, Right, Left, Up
+    /// and Down from bit 0.
+    pub fn joypad_state(&self) -> u8 {
+        self.joypad.state()
+    }
+
+    /// Set every button at once, with the layout of `joypad_state`.
+    ///
+    /// Like `game_input` this is ignored while a movie is playing.
+    pub fn set_joypad_state(&mut self, state: u8) {
+        #[cfg(feature = "std")]
+        if self.is_playing_movie() {
+            return;
+        }
+        self.joypad.set_state(self.cpu.memory_mut(), state)
+    }
+
+    /// CRC-32 of the loaded ROM, used to tell ROMs apart.
+    pub fn rom_crc(&self) -> u32 {
+        checksum::crc32(self.cpu.memory().rom())
+    }
+
+    #[cfg(feature = "std")]
+    /// Reset the system and record joypad input from power on.
+    ///
+    /// Input is sampled once per frame, so it should be given between calls to
+    /// `update`. Replaces any movie being recorded or played.
+    pub fn start_recording(&mut self) {
+        self.reset();
+        self.movie = MovieState::Recording(Movie::new(self.model(), self.rom_crc()));
+    }
+
+    #[cfg(feature = "std")]
+    /// Stop recording and take the movie, `None` if nothing was recorded.
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        match core::mem::replace(&mut self.movie, MovieState::Idle) {
+            MovieState::Recording(movie) => Some(movie),
+            state => {
+                self.movie = state;
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    /// Reset the system and play a movie back.
+    ///
+    /// Live input is ignored until the movie ends or `stop_movie` is called.
+    ///
+    /// Returns an error if the movie was recorded on another model or with a
+    /// different ROM.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), &'static str> {
+        if movie.model != self.model() {
+            return Err("Movie was recorded on a different model");
+        }
+        if movie.rom_crc != self.rom_crc() {
+            return Err("Movie was recorded with a different ROM");
+        }
+        let first = movie.frames.first().copied().unwrap_or_default();
+        self.joypad.set_state(self.cpu.memory_mut(), first);
+        self.reset();
+        self.movie = MovieState::Playing { movie, frame: 0 };
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    /// Stop playing a movie and go back to live input.
+    pub fn stop_movie(&mut self) {
+        if self.is_playing_movie() {
+            self.movie = MovieState::Idle;
+        }
+    }
+
+    #[cfg(feature = "std")]
+    /// Whether input is being recorded.
+    pub fn is_recording(&self) -> bool {
+        matches!(self.movie, MovieState::Recording(_))
+    }
+
+    #[cfg(feature = "std")]
+    /// Whether a movie is playing.
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, MovieState::Playing { .. })
+    }
+
+    #[cfg(feature = "std")]
+    /// Records or plays back the input for the frame that is starting.
+    fn advance_movie(&mut self) {
+        match &mut self.movie {
+            MovieState::Idle => {}
+            MovieState::Recording(movie) => movie.frames.push(self.joypad.state()),
+            MovieState::Playing { movie, frame } => match movie.frames.get(*frame) {
+                Some(state) => {
+                    *frame += 1;
+                    self.joypad.set_state(self.cpu.memory_mut(), *state);
+                }
+                None => self.movie = MovieState::Idle,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(100)]
+    fn test_cgb_colors_older_games() {
+        let mut rom = [0; 0x8000];
+        rom[0x134..0x13A].copy_from_slice(b"TETRIS");
+        rom[0x14B] = 0x01;
+
+        let mut emu = Emulator::with_model(Model::Cgb);
+        emu.load_rom_data(&rom);
+        assert_eq!(emu.palette().bg[1], [0xFF, 0xFF, 0x00]);
+
+        // DMG models stick to the configured palette
+        let mut emu = Emulator::new();
+        emu.set_palette(Palette::POCKET);
+        emu.load_rom_data(&rom);
+        assert_eq!(emu.palette(), Palette::POCKET);
+        assert_eq!(emu.cgb_compat_palette().bg[1], [0xFF, 0xFF, 0x00]);
+    }
+
+    #[test]
+    #[timeout(1000)]
+    fn test_update_stops_at_vblank() {
+        let mut rom = [0; 0x8000];
+        // JR -2
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        let mut emu = Emulator::new();
+        emu.load_rom_data(&rom);
+
+        for frame in 1..=3 {
+            assert!(emu.update());
+            assert_eq!(emu.frame_count(), frame);
+            let scanline = emu.cpu.memory().read_byte(crate::types::CURRENT_SCANLINE);
+            assert_eq!(scanline, 144);
+        }
+
+        emu.toggle_pause();
+        assert!(!emu.update());
+        assert_eq!(emu.frame_count(), 3);
+    }
+
+    #[test]
+    #[timeout(1000)]
+    fn test_frames_continue_with_lcd_off() {
+        let mut rom = [0; 0x8000];
+        // LD A, 0; LDH (0x40), A; JR -2
+        rom[0x100..0x106].copy_from_slice(&[0x3E, 0x00, 0xE0, 0x40, 0x18, 0xFE]);
+        let mut emu = Emulator::new();
+        emu.load_rom_data(&rom);
+
+        assert!(emu.update());
+        assert!(emu.update());
+        assert_eq!(emu.frame_count(), 2);
+        assert!(emu.frame_cycles < Emulator::FRAME_CYCLES);
+        assert!(emu.get_display_buffer().iter().all(|p| *p == 0xFF));
+    }
+
+    #[test]
+    #[timeout(1000)]
+    fn test_stepping() {
+        let mut rom = [0; 0x8000];
+        // NOP; LD A, 5; JP 0x0100
+        rom[0x100..0x106].copy_from_slice(&[0x00, 0x3E, 0x05, 0xC3, 0x00, 0x01]);
+        let mut emu = Emulator::new();
+        emu.load_rom_data(&rom);
+        emu.toggle_pause();
+
+        assert_eq!(emu.step_instruction(), 4);
+        assert_eq!(emu.pc(), 0x101);
+        assert_eq!(emu.step_instruction(), 8);
+        assert_eq!(emu.step_instruction(), 16);
+        assert_eq!(emu.pc(), 0x100);
+
+        // Each loop takes 28 cycles so runs overshoot to the end of an instruction
+        let result = emu.run_cycles(30);
+        assert_eq!(result.reason, StopReason::Cycles);
+        assert_eq!(result.cycles, 32);
+        assert_eq!(emu.pc(), 0x101);
+
+        let result = emu.run_until(1000, |emu| emu.pc() == 0x101);
+        assert_eq!(result.reason, StopReason::Predicate);
+        assert_eq!(result.cycles, 28);
+        assert_eq!(emu.pc(), 0x101);
+
+        let result = emu.run_until(1000, |emu| emu.pc() == 0x200);
+        assert_eq!(result.reason, StopReason::Cycles);
+        assert!(result.cycles >= 1000);
+
+        // Starting right after a frame ends, two frames' worth of cycles end
+        // exactly two more
+        let result = emu.run_until(Emulator::FRAME_CYCLES as u64, |emu| emu.frame_count() == 1);
+        assert_eq!(result.reason, StopReason::Predicate);
+        assert_eq!(result.frames, 1);
+
+        let result = emu.run_cycles(Emulator::FRAME_CYCLES as u64 * 2);
+        assert_eq!(result.frames, 2);
+        assert_eq!(emu.frame_count(), 3);
+    }
+
+    #[test]
+    #[timeout(1000)]
+    fn test_cheats() {
+        let mut rom = [0; 0x8000];
+        // LD A, (0x0150); LD (0xC100), A; JR -2
+        rom[0x100..0x108].copy_from_slice(&[0xFA, 0x50, 0x01, 0xEA, 0x00, 0xC1, 0x18, 0xFE]);
+        rom[0x150] = 0x11;
+        let mut emu = Emulator::new();
+        emu.load_rom_data(&rom);
+
+        // Patch 0x0150 from 0x11 to 0x22, and keep 0x33 at 0xC0D1
+        emu.cheats_mut().add("221-50F-A0E").unwrap();
+        emu.cheats_mut().add("0133D1C0").unwrap();
+        assert_eq!(emu.peek(0x150), 0x22);
+        emu.update();
+        assert_eq!(emu.peek(0xC100), 0x22);
+        assert_eq!(emu.peek(0xC0D1), 0x33);
+
+        emu.cheats_mut().set_enabled(0, false);
+        assert_eq!(emu.peek(0x150), 0x11);
+    }
+
+    #[test]
+    #[timeout(2000)]
+    fn test_movie_playback_matches_recording() {
+        let mut rom = [0; 0x8000];
+        // Select the buttons, then keep adding P1 to 0xC000
+        rom[0x100..0x10D].copy_from_slice(&[
+            0x3E, 0x10, 0xE0, 0x00, 0xF0, 0x00, 0x21, 0x00, 0xC0, 0x86, 0x77, 0x18, 0xF7,
+        ]);
+        let mut emu = Emulator::new();
+        emu.load_rom_data(&rom);
+        emu.update();
+
+        emu.start_recording();
+        assert!(emu.is_recording());
+        let inputs = [GameInput::A, GameInput::Start, GameInput::B, GameInput::A];
+        for input in inputs {
+            emu.game_input(input, KeyState::Pressed);
+            emu.update();
+        }
+        emu.game_input(GameInput::A, KeyState::Released);
+        emu.update();
+        let expected = emu.peek(0xC000);
+        let movie = emu.stop_recording().unwrap();
+        assert_eq!(movie.frames.len(), 5);
+        assert_eq!(movie.frames[1], 0x09);
+
+        emu.play_movie(movie.clone()).unwrap();
+        for _ in 0..5 {
+            // Live input doesn't get through during playback
+            emu.game_input(GameInput::Select, KeyState::Pressed);
+            emu.update();
+        }
+        assert_eq!(emu.peek(0xC000), expected);
+        assert!(emu.is_playing_movie());
+        emu.update();
+        assert!(!emu.is_playing_movie());
+
+        let mut other = Emulator::with_model(Model::Cgb);
+        other.load_rom_data(&rom);
+        assert!(other.play_movie(movie).is_err());
+    }
 }
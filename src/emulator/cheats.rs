@@ -0,0 +1,309 @@
+//! Game Genie and GameShark cheat codes
+use crate::types::{Byte, Word};
+
+/// Most codes that can be active at once
+pub const MAX_CHEATS: usize = 64;
+
+/// A decoded cheat code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatCode {
+    /// Replaces a byte read from ROM, written as `XXX-YYY` or `XXX-YYY-ZZZ`
+    GameGenie {
+        /// ROM address to patch, 0x0000 to 0x7FFF
+        address: Word,
+        /// Byte returned instead of the ROM contents
+        value: Byte,
+        /// Only patch when the ROM holds this byte, so the code only hits the
+        /// intended bank
+        compare: Option<Byte>,
+    },
+    /// Writes a byte to RAM every frame, written as `TTVVAAAA`
+    GameShark {
+        /// CGB WRAM bank for addresses at 0xD000-0xDFFF, `None` for whichever
+        /// bank is mapped
+        bank: Option<Byte>,
+        /// Address to write
+        address: Word,
+        /// Byte to write
+        value: Byte,
+    },
+}
+
+impl CheatCode {
+    /// Decodes a Game Genie or GameShark code
+    ///
+    /// Game Genie codes are nine or six hex digits split by dashes into groups
+    /// of three. GameShark codes are eight hex digits, a type byte (`01` for
+    /// the mapped bank, `9N` for WRAM bank N), the value and the address with
+    /// its low byte first.
+    pub fn parse(code: &str) -> Result<CheatCode, &'static str> {
+        let mut digits = [0; 9];
+        let mut len = 0;
+        let mut dashes = 0;
+        for c in code.trim().chars() {
+            if c == '-' {
+                dashes += 1;
+                continue;
+            }
+            let digit = c.to_digit(16).ok_or("Codes can only contain hex digits")?;
+            if len == digits.len() {
+                return Err("Code is too long");
+            }
+            digits[len] = digit as Byte;
+            len += 1;
+        }
+
+        match (len, dashes) {
+            (6, 1) | (9, 2) => Ok(Self::game_genie(&digits[..len])),
+            (8, 0) => Self::game_shark(&digits[..len]),
+            _ => Err("Not a Game Genie (XXX-YYY-ZZZ) or GameShark (TTVVAAAA) code"),
+        }
+    }
+
+    fn game_genie(d: &[Byte]) -> CheatCode {
+        let address = (((d[5] ^ 0xF) as Word) << 12)
+            | ((d[2] as Word) << 8)
+            | ((d[3] as Word) << 4)
+            | d[4] as Word;
+        // The middle digit of the last group is a checksum nobody agrees on
+        let compare = (d.len() == 9).then(|| ((d[6] << 4) | d[8]).rotate_right(2) ^ 0xBA);
+        CheatCode::GameGenie {
+            address,
+            value: (d[0] << 4) | d[1],
+            compare,
+        }
+    }
+
+    fn game_shark(d: &[Byte]) -> Result<CheatCode, &'static str> {
+        let byte = |i: usize| (d[i] << 4) | d[i + 1];
+        let bank = match byte(0) {
+            0x00 | 0x01 => None,
+            kind @ 0x90..=0x97 => Some((kind & 0x7).max(1)),
+            _ => return Err("Unsupported GameShark code type"),
+        };
+        Ok(CheatCode::GameShark {
+            bank,
+            address: ((byte(6) as Word) << 8) | byte(4) as Word,
+            value: byte(2),
+        })
+    }
+}
+
+/// A code and whether it is switched on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    /// The decoded code
+    pub code: CheatCode,
+    /// Whether the code is applied
+    pub enabled: bool,
+}
+
+/// The list of cheats applied to the running game
+#[derive(Clone, Copy, Debug)]
+pub struct Cheats {
+    cheats: [Option<Cheat>; MAX_CHEATS],
+    len: usize,
+}
+
+impl Default for Cheats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cheats {
+    /// An empty list
+    pub const fn new() -> Self {
+        Cheats {
+            cheats: [None; MAX_CHEATS],
+            len: 0,
+        }
+    }
+
+    /// Decodes a code and adds it, enabled
+    ///
+    /// Returns the index of the new cheat, or an error if the code is invalid
+    /// or the list is full.
+    pub fn add(&mut self, code: &str) -> Result<usize, &'static str> {
+        let code = CheatCode::parse(code)?;
+        if self.len == MAX_CHEATS {
+            return Err("Too many cheats");
+        }
+        self.cheats[self.len] = Some(Cheat {
+            code,
+            enabled: true,
+        });
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+
+    /// Adds the codes in a cheat file
+    ///
+    /// Each line holds a code, optionally followed by a description. Lines
+    /// starting with `#` and blank lines are skipped, and a code starting with
+    /// `!` is added disabled.
+    ///
+    /// Returns how many codes were added, or the line number and problem of
+    /// the first bad line. Codes before the bad line stay added.
+    pub fn load(&mut self, text: &str) -> Result<usize, (usize, &'static str)> {
+        let mut added = 0;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let code = line.split_whitespace().next().unwrap_or_default();
+            let (code, enabled) = match code.strip_prefix('!') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let index = self.add(code).map_err(|e| (number + 1, e))?;
+            self.set_enabled(index, enabled);
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    /// Removes a cheat, moving the ones after it down by one
+    pub fn remove(&mut self, index: usize) {
+        if index < self.len {
+            self.cheats.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+            self.cheats[self.len] = None;
+        }
+    }
+
+    /// Removes all cheats
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Switches a cheat on or off
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(Some(cheat)) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+    }
+
+    /// The cheat at an index
+    pub fn get(&self, index: usize) -> Option<&Cheat> {
+        self.cheats.get(index)?.as_ref()
+    }
+
+    /// Number of cheats in the list
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the list is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// All cheats in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats[..self.len].iter().flatten()
+    }
+
+    /// Applies the enabled Game Genie codes to a byte read from ROM
+    pub(crate) fn patch_rom(&self, addr: Word, original: Byte) -> Byte {
+        for cheat in self.iter().filter(|cheat| cheat.enabled) {
+            if let CheatCode::GameGenie {
+                address,
+                value,
+                compare,
+            } = cheat.code
+                && address == addr
+                && compare.is_none_or(|compare| compare == original)
+            {
+                return value;
+            }
+        }
+        original
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_parse_game_genie() {
+        assert_eq!(
+            CheatCode::parse("00A-17B-C49"),
+            Ok(CheatCode::GameGenie {
+                address: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            })
+        );
+        assert_eq!(
+            CheatCode::parse("3E1-5FE"),
+            Ok(CheatCode::GameGenie {
+                address: 0x115F,
+                value: 0x3E,
+                compare: None,
+            })
+        );
+        assert!(CheatCode::parse("00A17BC49").is_err());
+        assert!(CheatCode::parse("00A-17B-C4G").is_err());
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_parse_game_shark() {
+        assert_eq!(
+            CheatCode::parse("010FD1C0"),
+            Ok(CheatCode::GameShark {
+                bank: None,
+                address: 0xC0D1,
+                value: 0x0F,
+            })
+        );
+        assert_eq!(
+            CheatCode::parse("9363A0D2"),
+            Ok(CheatCode::GameShark {
+                bank: Some(3),
+                address: 0xD2A0,
+                value: 0x63,
+            })
+        );
+        assert!(CheatCode::parse("A10FD1C0").is_err());
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_rom_patch_compare() {
+        let mut cheats = Cheats::new();
+        cheats.add("00A-17B-C49").unwrap();
+        assert_eq!(cheats.patch_rom(0x4A17, 0xC8), 0x00);
+        // A different bank holds something else at the address
+        assert_eq!(cheats.patch_rom(0x4A17, 0x12), 0x12);
+        assert_eq!(cheats.patch_rom(0x4A18, 0xC8), 0xC8);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.patch_rom(0x4A17, 0xC8), 0xC8);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_load_cheat_file() {
+        let mut cheats = Cheats::new();
+        let file = "# Infinite lives\n010FD1C0 lives\n\n!3E1-5FE skip intro\n";
+        assert_eq!(cheats.load(file), Ok(2));
+        assert!(cheats.get(0).unwrap().enabled);
+        assert!(!cheats.get(1).unwrap().enabled);
+
+        let error = Err((2, "Codes can only contain hex digits"));
+        assert_eq!(cheats.load("010FD1C0\nnope"), error);
+        assert_eq!(cheats.len(), 3);
+
+        cheats.remove(0);
+        assert_eq!(cheats.len(), 2);
+        assert!(!cheats.get(0).unwrap().enabled);
+        cheats.clear();
+        assert!(cheats.is_empty());
+    }
+}
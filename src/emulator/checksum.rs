@@ -0,0 +1,32 @@
+//! Checksums for telling ROMs apart and for file formats
+
+/// CRC-32 as used by zip and PNG
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Continues a CRC-32 over more data, starting from `!0` and finishing with `!`
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+        let partial = crc32_update(!0, b"1234");
+        assert_eq!(!crc32_update(partial, b"56789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,240 @@
+//! Emulator settings and the builder that applies them
+use crate::emulator::{Emulator, Model, Palette};
+
+/// How closely CPU access to video memory follows the hardware
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryAccess {
+    /// VRAM and OAM can always be accessed, which hides timing bugs in games
+    /// and homebrew that touch video memory while the PPU is using it
+    #[default]
+    Permissive,
+    /// VRAM reads as 0xFF and ignores writes while the PPU is drawing (mode 3),
+    /// and OAM does the same during the OAM scan and drawing (modes 2 and 3)
+    Strict,
+}
+
+/// Layout of the pixels in [`Emulator::get_display_buffer`]
+///
+/// Pixels are stored row by row from the top left, 160 to a row.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Red, green and blue bytes
+    #[default]
+    Rgb888,
+    /// Red, green, blue and an opaque alpha byte
+    Rgba8888,
+    /// Blue, green, red and an opaque alpha byte
+    Bgra8888,
+    /// 5 bits red, 6 bits green and 5 bits blue packed into a little endian `u16`
+    Rgb565,
+    /// One byte per pixel holding the shade from 0 (lightest) to 3 (darkest)
+    ///
+    /// In CGB mode this is the color number within the tile's palette instead,
+    /// which says nothing about the actual color.
+    ShadeIndex,
+}
+
+impl PixelFormat {
+    /// Bytes taken up by one pixel
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb888 => 3,
+            PixelFormat::Rgba8888 | PixelFormat::Bgra8888 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::ShadeIndex => 1,
+        }
+    }
+}
+
+/// Something the emulator reports to a trace callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// The CPU is about to execute an instruction
+    Instruction {
+        /// Address of the instruction
+        pc: u16,
+        /// First byte of the instruction
+        opcode: u8,
+        /// AF register pair
+        af: u16,
+        /// BC register pair
+        bc: u16,
+        /// DE register pair
+        de: u16,
+        /// HL register pair
+        hl: u16,
+        /// Stack pointer
+        sp: u16,
+    },
+    /// The CPU jumped to an interrupt handler
+    Interrupt {
+        /// Address of the handler
+        vector: u16,
+    },
+}
+
+#[cfg(feature = "alloc")]
+/// Receives trace events
+///
+/// A boxed closure, so it can keep its own state such as a log file or a
+/// counter. Without the `alloc` feature it has to be a plain function.
+pub type TraceCallback = alloc::boxed::Box<dyn FnMut(&TraceEvent)>;
+#[cfg(not(feature = "alloc"))]
+/// Receives trace events
+///
+/// A plain function without the `alloc` feature, a boxed closure with it.
+pub type TraceCallback = fn(&TraceEvent);
+
+/// Which events are reported and where to
+#[derive(Default)]
+pub struct Trace {
+    /// Report every instruction before it executes
+    pub instructions: bool,
+    /// Report every interrupt dispatch
+    pub interrupts: bool,
+    /// Receives the events, nothing is traced without one
+    pub callback: Option<TraceCallback>,
+}
+
+impl core::fmt::Debug for Trace {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Trace")
+            .field("instructions", &self.instructions)
+            .field("interrupts", &self.interrupts)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+/// Settings for an [`Emulator`]
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Hardware to emulate
+    pub model: Model,
+    /// Colors used for DMG graphics
+    ///
+    /// Older games on [`Model::Cgb`] are colored by the boot ROM instead, see
+    /// [`Emulator::set_palette`] to override that.
+    pub palette: Palette,
+    /// Layout of the display buffer
+    pub pixel_format: PixelFormat,
+    /// Whether the PPU blocks CPU access to VRAM and OAM
+    pub memory_access: MemoryAccess,
+    /// CPU tracing
+    pub trace: Trace,
+}
+
+/// Builds an [`Emulator`] from a [`Config`] and an optional boot ROM
+///
+/// # Example
+/// ```
+/// use rbgb::{EmulatorBuilder, MemoryAccess, Model};
+///
+/// let emu = EmulatorBuilder::new()
+///     .model(Model::Cgb)
+///     .memory_access(MemoryAccess::Strict)
+///     .build()
+///     .unwrap();
+/// assert_eq!(emu.model(), Model::Cgb);
+/// ```
+#[derive(Debug, Default)]
+pub struct EmulatorBuilder<'a> {
+    config: Config,
+    boot_rom: Option<&'a [u8]>,
+}
+
+impl<'a> EmulatorBuilder<'a> {
+    /// Starts from the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts from an existing config
+    pub fn from_config(config: Config) -> Self {
+        EmulatorBuilder {
+            config,
+            boot_rom: None,
+        }
+    }
+
+    /// Hardware to emulate
+    pub fn model(mut self, model: Model) -> Self {
+        self.config.model = model;
+        self
+    }
+
+    /// Colors used for DMG graphics
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.config.palette = palette;
+        self
+    }
+
+    /// Layout of the display buffer
+    pub fn pixel_format(mut self, format: PixelFormat) -> Self {
+        self.config.pixel_format = format;
+        self
+    }
+
+    /// Boot ROM to run before each loaded ROM, see [`Emulator::set_boot_rom`]
+    pub fn boot_rom(mut self, data: &'a [u8]) -> Self {
+        self.boot_rom = Some(data);
+        self
+    }
+
+    /// Whether the PPU blocks CPU access to VRAM and OAM
+    pub fn memory_access(mut self, access: MemoryAccess) -> Self {
+        self.config.memory_access = access;
+        self
+    }
+
+    /// CPU tracing
+    pub fn trace(mut self, trace: Trace) -> Self {
+        self.config.trace = trace;
+        self
+    }
+
+    /// Creates the emulator
+    ///
+    /// Returns an error if the boot ROM is not a supported size.
+    pub fn build(self) -> Result<Emulator, &'static str> {
+        let mut emulator = Emulator::with_config(self.config);
+        if let Some(boot_rom) = self.boot_rom {
+            emulator.set_boot_rom(boot_rom)?;
+        }
+        Ok(emulator)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_builder_applies_config() {
+        let palette = Palette::uniform([[1, 2, 3], [4, 5, 6], [7, 8, 9], [10, 11, 12]]);
+        let emu = EmulatorBuilder::new()
+            .model(Model::Mgb)
+            .palette(palette)
+            .pixel_format(PixelFormat::Rgb565)
+            .memory_access(MemoryAccess::Strict)
+            .boot_rom(&[0; 0x100])
+            .build()
+            .unwrap();
+
+        let config = emu.config();
+        assert_eq!(config.model, Model::Mgb);
+        assert_eq!(config.palette, palette);
+        assert_eq!(config.pixel_format, PixelFormat::Rgb565);
+        assert_eq!(config.memory_access, MemoryAccess::Strict);
+        assert_eq!(emu.model(), Model::Mgb);
+        assert_eq!(emu.get_display_buffer().len(), 160 * 144 * 2);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_builder_rejects_bad_boot_rom() {
+        assert!(EmulatorBuilder::new().boot_rom(&[0; 0x10]).build().is_err());
+    }
+}
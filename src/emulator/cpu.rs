@@ -1,5 +1,7 @@
+use crate::emulator::config::{MemoryAccess, Trace, TraceEvent};
+use crate::emulator::graphics::Screen;
 use crate::emulator::mem::Memory;
-use crate::types::{DIVIDER_REGISTER, IE, IF, TIMA, TMA, TMC};
+use crate::types::{DIVIDER_REGISTER, IE, IF, LCD_CONTROL, LCD_STATUS, SPRITE_RAM};
 use registers::{
     CpuFlag::{C, H, N, Z},
     Registers,
@@ -11,55 +13,100 @@ mod registers;
 pub struct CPU {
     reg: Registers,
     mmu: MemoryAdapter,
-    pub timers: Timer,
     halted: bool,
+    /// STOP mode, the system clock is stopped until a selected joypad line goes low
+    stopped: bool,
     /// Accurate emulation means emulating that when interrupts are set, IME is off, and HALT is
     /// called, the PC fails to increment on the next byte instruction.
     /// https://github.com/geaz/emu-gameboy/blob/master/docs/The%20Cycle-Accurate%20Game%20Boy%20Docs.pdf
     halt_bug: bool,
     ime: bool,
-    setdi: u32,
-    setei: u32,
+    /// Set by EI, IME is only turned on after the instruction that follows it
+    ei_delay: bool,
+    trace: Trace,
 }
 
 impl CPU {
     pub fn new() -> CPU {
         let registers = Registers::new();
-        let timers = Timer::new();
         CPU {
             reg: registers,
             mmu: MemoryAdapter::new(),
-            timers,
             halted: false,
+            stopped: false,
             halt_bug: false,
-            ime: true,
-            setdi: 0,
-            setei: 0,
+            ime: false,
+            ei_delay: false,
+            trace: Trace::default(),
         }
     }
 
+    pub fn set_memory_access(&mut self, access: MemoryAccess) {
+        self.mmu.access = access;
+    }
+
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = trace;
+    }
+
     pub fn reset(&mut self) {
-        self.reg.reset();
+        // A mapped boot ROM starts from 0x0000 and sets up the registers itself
+        if self.mmu.mem.boot_rom_mapped() {
+            self.reg = Registers::power_on();
+        } else {
+            let mem = &self.mmu.mem;
+            self.reg = Registers::for_model(
+                mem.model(),
+                mem.cgb_mode(),
+                mem.read_byte(0x014D),
+                mem.title_checksum(),
+            );
+        }
         self.halted = false;
+        self.stopped = false;
         self.halt_bug = false;
-        self.ime = true;
-        self.setdi = 0;
-        self.setei = 0;
+        self.ime = false;
+        self.ei_delay = false;
     }
 
+    /// Runs one instruction (or interrupt dispatch) and returns the T-cycles it took
+    ///
+    /// The timer, PPU, DMA and serial port are stepped along with every M-cycle
+    /// of the instruction rather than after it. Cycles are counted at normal
+    /// speed, so an instruction in CGB double speed mode reports half as many.
     pub fn execute_next_opcode(&mut self, _extension: bool) -> i64 {
-        (self.docycle() * 4) as i64
+        self.mmu.cycles = 0;
+        self.mmu.stalled = 0;
+
+        // Nothing is clocked in STOP mode, time still passes for the caller though
+        if self.stopped {
+            if !self.mmu.mem.joypad_line_low() {
+                return 4;
+            }
+            self.stopped = false;
+        }
+
+        let m_cycles = self.docycle();
+        self.mmu.idle_until(m_cycles);
+
+        let cycles = self.mmu.cycles + self.mmu.stalled;
+        if self.mmu.mem.double_speed() {
+            (cycles / 2) as i64
+        } else {
+            cycles as i64
+        }
     }
 
-    pub fn handle_interrupts(&mut self) {
-        self.handleinterrupt();
+    /// Address of the next instruction
+    pub fn pc(&self) -> u16 {
+        self.reg.pc
     }
 
-    pub fn update_timers(&mut self, cycles: i32) {
-        self.timers.update_timers(&mut self.mmu.mem, cycles);
+    /// Whether the system clock is stopped by STOP, which also halts the PPU
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
     }
 
-    #[cfg(feature = "std")]
     pub fn memory(&self) -> &Memory {
         &self.mmu.mem
     }
@@ -68,19 +115,45 @@ impl CPU {
         &mut self.mmu.mem
     }
 
+    pub fn screen(&self) -> &Screen {
+        &self.mmu.screen
+    }
+
+    pub fn screen_mut(&mut self) -> &mut Screen {
+        &mut self.mmu.screen
+    }
+
     fn docycle(&mut self) -> u32 {
-        self.updateime();
-        match self.handleinterrupt() {
-            0 => {}
-            n => return n,
-        };
+        let mut cycles = 0;
 
+        // HALT is left as soon as an enabled interrupt is requested, even with IME off
         if self.halted {
-            // Emulate a noop instruction
-            1
-        } else {
-            self.call()
+            if self.mmu.pending_interrupts() == 0 {
+                // Emulate a noop instruction
+                return 1;
+            }
+            self.halted = false;
+            self.mmu.tick();
+            cycles += 1;
+        }
+
+        if self.ime && self.mmu.pending_interrupts() != 0 {
+            return cycles + self.handleinterrupt();
         }
+
+        let ei_delay = self.ei_delay;
+        if ei_delay {
+            self.ime = true;
+        }
+        if self.trace.instructions {
+            self.trace_instruction();
+        }
+        cycles += self.call();
+        if ei_delay {
+            self.ei_delay = false;
+        }
+
+        cycles
     }
 
     fn fetchbyte(&mut self) -> u8 {
@@ -99,59 +172,67 @@ impl CPU {
         w
     }
 
-    fn updateime(&mut self) {
-        self.setdi = match self.setdi {
-            2 => 1,
-            1 => {
-                self.ime = false;
-                0
-            }
-            _ => 0,
-        };
-        self.setei = match self.setei {
-            2 => 1,
-            1 => {
-                self.ime = true;
-                0
-            }
-            _ => 0,
-        };
-    }
-
+    /// Dispatches the highest priority pending interrupt, taking 5 M-cycles
+    ///
+    /// The vector is only picked after the high byte of PC has been pushed. If
+    /// that write lands on IE and disables the pending interrupt the dispatch is
+    /// cancelled and execution continues at 0x0000 instead.
     fn handleinterrupt(&mut self) -> u32 {
-        if !self.ime && !self.halted {
-            return 0;
-        }
+        self.ime = false;
+
+        // Two wait states while the CPU discards its prefetch and decrements SP
+        self.mmu.tick();
+        self.mmu.tick();
+
+        let pc = self.reg.pc;
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.mmu.wb(self.reg.sp, (pc >> 8) as u8);
+
+        let triggered = self.mmu.pending_interrupts();
+
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.mmu.wb(self.reg.sp, pc as u8);
 
-        let ie = self.mmu.read_ie();
-        let mut intf = self.mmu.read_if();
-        let triggered = ie & intf & 0x1F;
         if triggered == 0 {
-            return 0;
+            self.reg.pc = 0x0000;
+        } else {
+            let n = triggered.trailing_zeros();
+            let intf = self.mmu.read_if() & !(1 << n);
+            self.mmu.write_if(intf);
+            self.reg.pc = 0x0040 | ((n as u16) << 3);
         }
 
-        self.halted = false;
-        if !self.ime {
-            return 0;
+        if let (true, Some(callback)) = (self.trace.interrupts, &mut self.trace.callback) {
+            callback(&TraceEvent::Interrupt {
+                vector: self.reg.pc,
+            });
         }
-        self.ime = false;
 
-        let n = triggered.trailing_zeros();
-        if n >= 5 {
-            panic!("Invalid interrupt triggered");
-        }
-        intf &= !(1 << n);
-        self.mmu.write_if(intf);
-        let pc = self.reg.pc;
-        self.pushstack(pc);
-        self.reg.pc = 0x0040 | ((n as u16) << 3);
+        5
+    }
 
-        4
+    /// Reports the instruction about to run without touching the bus
+    fn trace_instruction(&mut self) {
+        if let Some(callback) = &mut self.trace.callback {
+            callback(&TraceEvent::Instruction {
+                pc: self.reg.pc,
+                opcode: self.mmu.mem.read_byte(self.reg.pc),
+                af: self.reg.af(),
+                bc: self.reg.bc(),
+                de: self.reg.de(),
+                hl: self.reg.hl(),
+                sp: self.reg.sp,
+            });
+        }
     }
 
     fn pushstack(&mut self, value: u16) {
-        self.reg.sp = self.reg.sp.wrapping_sub(2);
-        self.mmu.ww(self.reg.sp, value);
+        // SP is decremented on an internal cycle, then the high byte is written first
+        self.mmu.tick();
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.mmu.wb(self.reg.sp, (value >> 8) as u8);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.mmu.wb(self.reg.sp, value as u8);
     }
 
     fn popstack(&mut self) -> u16 {
@@ -228,10 +309,7 @@ impl CPU {
                 self.reg.flag(Z, false);
                 1
             }
-            0x10 => {
-                self.mmu.switch_speed();
-                1
-            } // STOP
+            0x10 => self.cpu_stop(),
             0x11 => {
                 let v = self.fetchword();
                 self.reg.setde(v);
@@ -660,8 +738,15 @@ impl CPU {
                 2
             }
             0x76 => {
-                self.halted = true;
-                self.halt_bug = (self.mmu.read_if() & self.mmu.read_ie() & 0x1F) != 0;
+                if self.mmu.pending_interrupts() == 0 {
+                    self.halted = true;
+                } else if !self.ime {
+                    // HALT exits immediately and the next byte is read twice
+                    self.halt_bug = true;
+                } else if self.ei_delay {
+                    // EI right before HALT returns from the interrupt to the HALT itself
+                    self.reg.pc = self.reg.pc.wrapping_sub(1);
+                }
                 1
             }
             0x77 => {
@@ -963,6 +1048,7 @@ impl CPU {
             }
             0xC0 => {
                 if !self.reg.getflag(Z) {
+                    self.mmu.tick();
                     self.reg.pc = self.popstack();
                     5
                 } else {
@@ -989,8 +1075,9 @@ impl CPU {
             }
             0xC4 => {
                 if !self.reg.getflag(Z) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchword();
+                    let a = self.fetchword();
+                    self.pushstack(self.reg.pc);
+                    self.reg.pc = a;
                     6
                 } else {
                     self.reg.pc += 2;
@@ -1013,6 +1100,7 @@ impl CPU {
             }
             0xC8 => {
                 if self.reg.getflag(Z) {
+                    self.mmu.tick();
                     self.reg.pc = self.popstack();
                     5
                 } else {
@@ -1035,8 +1123,9 @@ impl CPU {
             0xCB => self.call_cb(),
             0xCC => {
                 if self.reg.getflag(Z) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchword();
+                    let a = self.fetchword();
+                    self.pushstack(self.reg.pc);
+                    self.reg.pc = a;
                     6
                 } else {
                     self.reg.pc += 2;
@@ -1044,8 +1133,9 @@ impl CPU {
                 }
             }
             0xCD => {
-                self.pushstack(self.reg.pc + 2);
-                self.reg.pc = self.fetchword();
+                let a = self.fetchword();
+                self.pushstack(self.reg.pc);
+                self.reg.pc = a;
                 6
             }
             0xCE => {
@@ -1060,6 +1150,7 @@ impl CPU {
             }
             0xD0 => {
                 if !self.reg.getflag(C) {
+                    self.mmu.tick();
                     self.reg.pc = self.popstack();
                     5
                 } else {
@@ -1082,8 +1173,9 @@ impl CPU {
             }
             0xD4 => {
                 if !self.reg.getflag(C) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchword();
+                    let a = self.fetchword();
+                    self.pushstack(self.reg.pc);
+                    self.reg.pc = a;
                     6
                 } else {
                     self.reg.pc += 2;
@@ -1106,6 +1198,7 @@ impl CPU {
             }
             0xD8 => {
                 if self.reg.getflag(C) {
+                    self.mmu.tick();
                     self.reg.pc = self.popstack();
                     5
                 } else {
@@ -1114,7 +1207,7 @@ impl CPU {
             }
             0xD9 => {
                 self.reg.pc = self.popstack();
-                self.setei = 1;
+                self.ime = true;
                 4
             }
             0xDA => {
@@ -1128,8 +1221,9 @@ impl CPU {
             }
             0xDC => {
                 if self.reg.getflag(C) {
-                    self.pushstack(self.reg.pc + 2);
-                    self.reg.pc = self.fetchword();
+                    let a = self.fetchword();
+                    self.pushstack(self.reg.pc);
+                    self.reg.pc = a;
                     6
                 } else {
                     self.reg.pc += 2;
@@ -1212,7 +1306,8 @@ impl CPU {
                 2
             }
             0xF3 => {
-                self.setdi = 2;
+                self.ime = false;
+                self.ei_delay = false;
                 1
             }
             0xF5 => {
@@ -1244,7 +1339,7 @@ impl CPU {
                 4
             }
             0xFB => {
-                self.setei = 2;
+                self.ei_delay = !self.ime;
                 1
             }
             0xFE => {
@@ -2540,93 +2635,667 @@ impl CPU {
         self.reg.a = a;
     }
 
+    /// STOP, whose length and effect depend on the joypad and pending interrupts
+    fn cpu_stop(&mut self) -> u32 {
+        let interrupt_pending = self.mmu.pending_interrupts() != 0;
+
+        if self.mmu.mem.joypad_line_low() {
+            if interrupt_pending {
+                return 1;
+            }
+            // With a button held STOP falls back to HALT and DIV keeps running
+            self.fetchbyte();
+            self.halted = true;
+            return 2;
+        }
+
+        // A speed switch requested through KEY1 happens instead of stopping
+        if self.mmu.mem.switch_speed() {
+            self.fetchbyte();
+            self.mmu.mem.write_byte(DIVIDER_REGISTER, 0);
+            return 2;
+        }
+
+        // The operand is only skipped when nothing is pending
+        let cycles = if interrupt_pending {
+            1
+        } else {
+            self.fetchbyte();
+            2
+        };
+        self.mmu.mem.write_byte(DIVIDER_REGISTER, 0);
+        self.mmu.screen.blank();
+        self.stopped = true;
+        cycles
+    }
+
     fn cpu_jr(&mut self) {
         let n = self.fetchbyte() as i8;
         self.reg.pc = ((self.reg.pc as u32 as i32) + (n as i32)) as u16;
     }
 }
 
+/// The CPU's view of the rest of the system
+///
+/// Every bus access takes one M-cycle and steps the timer, PPU, DMA and serial
+/// port before the access happens. Cycles an instruction spends off the bus are
+/// run afterwards by `idle_until`.
 struct MemoryAdapter {
     mem: Memory,
+    screen: Screen,
+    /// T-cycles run so far by the current instruction
+    cycles: u32,
+    /// T-cycles the CPU spent held by a VRAM DMA during the current instruction
+    stalled: u32,
+    access: MemoryAccess,
 }
 
 impl MemoryAdapter {
     fn new() -> Self {
-        Self { mem: Memory::new() }
+        Self {
+            mem: Memory::new(),
+            screen: Screen::new(),
+            cycles: 0,
+            stalled: 0,
+            access: MemoryAccess::Permissive,
+        }
+    }
+
+    /// Advances everything outside of the CPU by one M-cycle
+    fn tick(&mut self) {
+        self.cycles += 4;
+        self.step();
+
+        // The CPU sits idle while a VRAM DMA copies, the rest of the system doesn't
+        let stall = self.mem.take_dma_stall();
+        for _ in 0..stall {
+            self.stalled += 4;
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        // The PPU runs at the same speed in double speed mode, so it sees half the cycles
+        let ppu_cycles = if self.mem.double_speed() { 2 } else { 4 };
+        self.mem.update_timers(4);
+        self.mem.update_serial(4);
+        self.mem.update_dma();
+        self.screen.update_screen(&mut self.mem, ppu_cycles);
     }
 
-    fn rb(&self, address: u16) -> u8 {
+    /// Runs internal cycles until the instruction has taken `m_cycles` in total
+    fn idle_until(&mut self, m_cycles: u32) {
+        debug_assert!(
+            self.cycles <= m_cycles * 4,
+            "Instruction accessed the bus more often than its cycle count"
+        );
+        while self.cycles < m_cycles * 4 {
+            self.tick();
+        }
+    }
+
+    fn rb(&mut self, address: u16) -> u8 {
+        self.tick();
+        if self.blocked(address) {
+            return 0xFF;
+        }
         self.mem.read_byte(address)
     }
 
     fn wb(&mut self, address: u16, value: u8) {
+        self.tick();
+        if self.blocked(address) {
+            return;
+        }
         self.mem.write_byte(address, value);
     }
 
-    fn rw(&self, address: u16) -> u16 {
-        self.mem.read_word(address)
+    /// Whether the OAM DMA or the PPU is keeping the CPU away from `address`
+    fn blocked(&self, address: u16) -> bool {
+        let oam = (SPRITE_RAM..0xFEA0).contains(&address);
+        // OAM is taken over by the DMA while it runs
+        if oam && self.mem.dma_active() {
+            return true;
+        }
+
+        if self.access == MemoryAccess::Permissive || self.mem.read_byte(LCD_CONTROL) & 0x80 == 0 {
+            return false;
+        }
+        let mode = self.mem.read_byte(LCD_STATUS) & 0x3;
+        match address {
+            0x8000..=0x9FFF => mode == 3,
+            _ if oam => mode >= 2,
+            _ => false,
+        }
+    }
+
+    fn rw(&mut self, address: u16) -> u16 {
+        let l = self.rb(address) as u16;
+        let h = self.rb(address.wrapping_add(1)) as u16;
+        h << 8 | l
     }
 
     fn ww(&mut self, address: u16, value: u16) {
-        self.mem.write_word(address, value);
+        self.wb(address, value as u8);
+        self.wb(address.wrapping_add(1), (value >> 8) as u8);
     }
 
     fn read_ie(&self) -> u8 {
-        self.rb(IE)
+        self.mem.read_byte(IE)
+    }
+
+    /// Interrupts that are both requested and enabled
+    fn pending_interrupts(&self) -> u8 {
+        self.read_ie() & self.read_if() & 0x1F
     }
 
     fn read_if(&self) -> u8 {
-        self.rb(IF)
+        self.mem.read_byte(IF)
     }
 
     fn write_if(&mut self, value: u8) {
-        self.wb(IF, value);
+        self.mem.write_byte(IF, value);
     }
-
-    fn switch_speed(&self) {}
 }
 
-pub struct Timer {
-    divider_counter: u32,
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::model::Model;
+    use crate::types::{DMA_REG, INPUT_REGISTER};
+    use ntest::timeout;
+    use std::{boxed::Box, cell::Cell, rc::Rc};
+
+    fn load_program(cpu: &mut CPU, program: &[u8]) {
+        for (i, byte) in program.iter().enumerate() {
+            cpu.memory_mut().write_byte_forced(0x100 + i as u16, *byte);
+        }
+    }
 
-impl Timer {
-    pub fn new() -> Self {
-        Timer { divider_counter: 0 }
+    fn request_vblank(cpu: &mut CPU) {
+        cpu.memory_mut().write_byte(IE, 0x01);
+        cpu.memory_mut().write_byte(IF, 0x01);
     }
 
-    pub fn update_timers(&mut self, mem: &mut Memory, cycles: i32) {
-        self.do_divider_registers(mem, cycles);
+    fn read_stack_word(cpu: &mut CPU) -> u16 {
+        let sp = cpu.reg.sp;
+        cpu.memory_mut().read_word(sp)
+    }
 
-        if Self::is_clock_enabled(mem) {
-            mem.timer_counter -= cycles;
+    #[test]
+    #[timeout(100)]
+    fn test_read_happens_on_last_cycle() {
+        // LDH A,(DIV) reads on its third M-cycle
+        for (start, expected) in [(240, 0), (244, 1)] {
+            let mut cpu = CPU::new();
+            load_program(&mut cpu, &[0xF0, 0x04]);
+            cpu.memory_mut().write_byte(DIVIDER_REGISTER, 0);
+            cpu.memory_mut().update_timers(start);
+
+            assert_eq!(cpu.execute_next_opcode(false), 12);
+            assert_eq!(cpu.reg.a, expected);
+        }
+    }
 
-            if mem.timer_counter <= 0 {
-                mem.set_clock_frequency();
+    #[test]
+    #[timeout(100)]
+    fn test_call_pushes_high_byte_first() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0xCD, 0x34, 0x12]);
+        cpu.reg.sp = 0xD000;
+
+        assert_eq!(cpu.execute_next_opcode(false), 24);
+        assert_eq!(cpu.reg.pc, 0x1234);
+        assert_eq!(cpu.reg.sp, 0xCFFE);
+        assert_eq!(cpu.memory_mut().read_byte(0xCFFF), 0x01);
+        assert_eq!(cpu.memory_mut().read_byte(0xCFFE), 0x03);
+    }
 
-                if mem.read_byte(TIMA) == 255 {
-                    let tma_val = mem.read_byte(TMA);
-                    mem.write_byte(TIMA, tma_val);
-                    mem.request_interrupt(2);
-                } else {
-                    let tima_val = mem.read_byte(TIMA).wrapping_add(1);
-                    mem.write_byte(TIMA, tima_val);
-                }
-            }
+    #[test]
+    #[timeout(100)]
+    fn test_internal_cycles_advance_timer() {
+        // INC BC spends its second M-cycle off the bus
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x03]);
+        cpu.memory_mut().write_byte(DIVIDER_REGISTER, 0);
+        cpu.memory_mut().update_timers(248);
+
+        assert_eq!(cpu.execute_next_opcode(false), 8);
+        assert_eq!(cpu.memory_mut().read_byte(DIVIDER_REGISTER), 1);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_oam_blocked_during_dma() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0xFA, 0x00, 0xFE, 0xFA, 0x00, 0xFE]);
+        cpu.memory_mut().write_byte_forced(0xFE00, 0x55);
+        cpu.memory_mut().write_byte_forced(0xC000, 0x11);
+        cpu.memory_mut().write_byte(DMA_REG, 0xC0);
+
+        // LD A,(nn) reads OAM after the DMA has started copying
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.a, 0xFF);
+
+        for _ in 0..0xA0 {
+            cpu.memory_mut().update_dma();
+        }
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.a, 0x11);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_interrupt_dispatch_takes_five_cycles() {
+        let mut cpu = CPU::new();
+        cpu.reg.sp = 0xD000;
+        cpu.ime = true;
+        request_vblank(&mut cpu);
+
+        assert_eq!(cpu.execute_next_opcode(false), 20);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(read_stack_word(&mut cpu), 0x0100);
+        assert_eq!(cpu.memory_mut().read_byte(IF), 0x00);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_ie_write_during_push_cancels_dispatch() {
+        // Pushing PC=0x0200 with SP=0x0000 writes 0x02 into IE
+        let mut cpu = CPU::new();
+        cpu.reg.pc = 0x0200;
+        cpu.reg.sp = 0x0000;
+        cpu.ime = true;
+        request_vblank(&mut cpu);
+
+        assert_eq!(cpu.execute_next_opcode(false), 20);
+        assert_eq!(cpu.reg.pc, 0x0000);
+        assert_eq!(cpu.memory_mut().read_byte(IF), 0x01);
+        assert_eq!(cpu.memory_mut().read_byte(IE), 0x02);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_ie_write_during_push_redirects_dispatch() {
+        let mut cpu = CPU::new();
+        cpu.reg.pc = 0x0200;
+        cpu.reg.sp = 0x0000;
+        cpu.ime = true;
+        cpu.memory_mut().write_byte(IE, 0x03);
+        cpu.memory_mut().write_byte(IF, 0x03);
+
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.pc, 0x0048);
+        assert_eq!(cpu.memory_mut().read_byte(IF), 0x01);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_ei_enables_after_next_instruction() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0xFB, 0x00, 0x00]); // EI, NOP, NOP
+        cpu.reg.sp = 0xD000;
+        request_vblank(&mut cpu);
+
+        cpu.execute_next_opcode(false);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.reg.pc, 0x0101);
+
+        // The NOP still runs before the interrupt is taken
+        cpu.execute_next_opcode(false);
+        assert!(cpu.ime);
+        assert_eq!(cpu.reg.pc, 0x0102);
+
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(read_stack_word(&mut cpu), 0x0102);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_ei_di_never_enables() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0xFB, 0xF3, 0x00]); // EI, DI, NOP
+        request_vblank(&mut cpu);
+
+        for _ in 0..3 {
+            cpu.execute_next_opcode(false);
+        }
+        assert!(!cpu.ime);
+        assert_eq!(cpu.reg.pc, 0x0103);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_di_is_immediate() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0xF3, 0x00]); // DI, NOP
+        cpu.ime = true;
+
+        cpu.execute_next_opcode(false);
+        request_vblank(&mut cpu);
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.pc, 0x0102);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_reti_enables_immediately() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0xD9]); // RETI
+        cpu.reg.sp = 0xCFFE;
+        cpu.memory_mut().write_byte(0xCFFE, 0x00);
+        cpu.memory_mut().write_byte(0xCFFF, 0x02);
+        request_vblank(&mut cpu);
+
+        assert_eq!(cpu.execute_next_opcode(false), 16);
+        assert!(cpu.ime);
+        assert_eq!(cpu.reg.pc, 0x0200);
+
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.pc, 0x0040);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_halt_exit_without_ime() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x76, 0x00]); // HALT, NOP
+        cpu.memory_mut().write_byte(IE, 0x01);
+
+        cpu.execute_next_opcode(false);
+        assert!(cpu.halted);
+        assert_eq!(cpu.execute_next_opcode(false), 4);
+        assert!(cpu.halted);
+
+        // Waking takes one M-cycle then the NOP runs, the interrupt stays requested
+        cpu.memory_mut().write_byte(IF, 0x01);
+        assert_eq!(cpu.execute_next_opcode(false), 8);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.reg.pc, 0x0102);
+        assert_eq!(cpu.memory_mut().read_byte(IF), 0x01);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_halt_exit_with_ime() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x76, 0x00]); // HALT, NOP
+        cpu.reg.sp = 0xD000;
+        cpu.ime = true;
+        cpu.memory_mut().write_byte(IE, 0x01);
+
+        cpu.execute_next_opcode(false);
+        assert!(cpu.halted);
+
+        cpu.memory_mut().write_byte(IF, 0x01);
+        assert_eq!(cpu.execute_next_opcode(false), 24);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(read_stack_word(&mut cpu), 0x0101);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_halt_bug_repeats_next_byte() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x76, 0x3C, 0x00]); // HALT, INC A, NOP
+        cpu.reg.a = 0;
+        request_vblank(&mut cpu);
+
+        cpu.execute_next_opcode(false);
+        assert!(!cpu.halted);
+
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.pc, 0x0101);
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.pc, 0x0102);
+        assert_eq!(cpu.reg.a, 2);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_ei_before_halt_returns_to_halt() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0xFB, 0x76, 0x00]); // EI, HALT, NOP
+        cpu.reg.sp = 0xD000;
+        request_vblank(&mut cpu);
+
+        cpu.execute_next_opcode(false);
+        cpu.execute_next_opcode(false);
+        assert!(!cpu.halted);
+
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(read_stack_word(&mut cpu), 0x0101);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_stop_waits_for_joypad() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x10, 0x00, 0x3C]); // STOP, INC A
+        cpu.reg.a = 0;
+        cpu.memory_mut().write_byte(INPUT_REGISTER, 0x10); // select buttons
+        cpu.memory_mut().update_timers(0x400);
+
+        cpu.execute_next_opcode(false);
+        assert!(cpu.stopped);
+        assert_eq!(cpu.reg.pc, 0x0102);
+        assert_eq!(cpu.memory_mut().read_byte(DIVIDER_REGISTER), 0);
+        assert!(cpu.screen().buffer.iter().all(|p| *p == 0xFF));
+
+        // The system clock is stopped so DIV doesn't move
+        for _ in 0..100 {
+            assert_eq!(cpu.execute_next_opcode(false), 4);
         }
+        assert_eq!(cpu.memory_mut().read_byte(DIVIDER_REGISTER), 0);
+        assert_eq!(cpu.reg.a, 0);
+
+        cpu.memory_mut().update_joypad_state(0x0E, 0x0F); // A pressed
+        cpu.execute_next_opcode(false);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.reg.a, 1);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_stop_ignores_unselected_lines() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x10, 0x00]);
+        cpu.memory_mut().write_byte(INPUT_REGISTER, 0x20); // select directions
+
+        cpu.execute_next_opcode(false);
+        cpu.memory_mut().update_joypad_state(0x0E, 0x0F); // A pressed
+        cpu.execute_next_opcode(false);
+        assert!(cpu.stopped);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_stop_with_button_held_halts() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x10, 0x00]);
+        cpu.memory_mut().write_byte(INPUT_REGISTER, 0x10);
+        cpu.memory_mut().update_joypad_state(0x0E, 0x0F);
+        cpu.memory_mut().update_timers(0x400);
+
+        cpu.execute_next_opcode(false);
+        assert!(!cpu.stopped);
+        assert!(cpu.halted);
+        assert_eq!(cpu.reg.pc, 0x0102);
+        assert_eq!(cpu.memory_mut().read_byte(DIVIDER_REGISTER), 4);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_stop_with_interrupt_pending_is_one_byte() {
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x10, 0x00]);
+        cpu.memory_mut().write_byte(INPUT_REGISTER, 0x10);
+        request_vblank(&mut cpu);
+
+        cpu.execute_next_opcode(false);
+        assert!(cpu.stopped);
+        assert_eq!(cpu.reg.pc, 0x0101);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_stop_switches_speed_on_cgb() {
+        let mut cpu = CPU::new();
+        let mut rom = [0; 0x8000];
+        rom[0x143] = 0x80;
+        // LD A,1; LDH (KEY1),A; STOP; NOP
+        rom[0x100..0x107].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x00]);
+        cpu.memory_mut().set_model(Model::Cgb);
+        cpu.memory_mut().load_rom_data(&rom);
+        cpu.memory_mut().ram_startup();
+        cpu.reset();
+        assert_eq!(cpu.reg.a, 0x11);
+        cpu.memory_mut().write_byte(INPUT_REGISTER, 0x30);
+
+        cpu.execute_next_opcode(false);
+        cpu.execute_next_opcode(false);
+        cpu.execute_next_opcode(false);
+        assert!(!cpu.stopped);
+        assert!(cpu.memory_mut().double_speed());
+        assert_eq!(cpu.reg.pc, 0x0106);
+        assert_eq!(cpu.memory_mut().read_byte(0xFF4D), 0xFE);
+
+        // Instructions take half as long in double speed
+        assert_eq!(cpu.execute_next_opcode(false), 2);
     }
 
-    fn do_divider_registers(&mut self, mem: &mut Memory, cycles: i32) {
-        self.divider_counter += cycles as u32;
-        if self.divider_counter >= 255 {
-            self.divider_counter = 0;
-            let divider_register = mem.read_byte_forced(DIVIDER_REGISTER).wrapping_add(1);
-            mem.write_byte_forced(DIVIDER_REGISTER, divider_register);
+    #[test]
+    #[timeout(100)]
+    fn test_boot_rom_hands_over_at_0x100() {
+        let mut cpu = CPU::new();
+        let mut boot = [0; 0x100];
+        // LD A,1; LDH (0xFF50),A at the very end of the boot ROM
+        boot[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        cpu.memory_mut().set_boot_rom(&boot).unwrap();
+        cpu.memory_mut().load_rom_data(&[0x3C; 0x8000]);
+        cpu.reset();
+        assert_eq!(cpu.reg.pc, 0x0000);
+        assert_eq!(cpu.reg.af(), 0x0000);
+
+        while cpu.reg.pc < 0x100 {
+            cpu.execute_next_opcode(false);
         }
+        assert!(!cpu.memory_mut().boot_rom_mapped());
+        assert_eq!(cpu.reg.a, 0x01);
+        assert_eq!(cpu.memory_mut().read_byte(0x0000), 0x3C);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_post_boot_registers_per_model() {
+        let mut rom = [0; 0x8000];
+        rom[0x14D] = 0x42; // header checksum
+        let tests = [
+            (Model::Dmg0, 0x0100, 0xFF13, 0x00C1, 0x8403),
+            (Model::Dmg, 0x01B0, 0x0013, 0x00D8, 0x014D),
+            (Model::Mgb, 0xFFB0, 0x0013, 0x00D8, 0x014D),
+            (Model::Sgb, 0x0100, 0x0014, 0x0000, 0xC060),
+            (Model::Sgb2, 0xFF00, 0x0014, 0x0000, 0xC060),
+            // DMG compatibility mode since the cartridge has no CGB flag
+            (Model::Cgb, 0x1180, 0x0000, 0x0008, 0x007C),
+        ];
+
+        for (model, af, bc, de, hl) in tests {
+            let mut cpu = CPU::new();
+            cpu.memory_mut().set_model(model);
+            cpu.memory_mut().load_rom_data(&rom);
+            cpu.memory_mut().ram_startup();
+            cpu.reset();
+            assert_eq!(
+                (cpu.reg.af(), cpu.reg.bc(), cpu.reg.de(), cpu.reg.hl()),
+                (af, bc, de, hl),
+                "{model:?}"
+            );
+            assert_eq!(cpu.reg.sp, 0xFFFE);
+            assert_eq!(cpu.reg.pc, 0x0100);
+        }
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_cgb_compat_registers_use_title_checksum() {
+        let mut rom = [0; 0x8000];
+        rom[0x134] = 0x43;
+        rom[0x14B] = 0x01;
+        let mut cpu = CPU::new();
+        cpu.memory_mut().set_model(Model::Cgb);
+        cpu.memory_mut().load_rom_data(&rom);
+        cpu.memory_mut().ram_startup();
+        cpu.reset();
+        assert_eq!(cpu.reg.b, 0x43);
+        assert_eq!(cpu.reg.hl(), 0x991A);
+
+        // CGB mode ignores the title
+        rom[0x143] = 0x80;
+        cpu.memory_mut().load_rom_data(&rom);
+        cpu.memory_mut().ram_startup();
+        cpu.reset();
+        assert_eq!(cpu.reg.af(), 0x1180);
+        assert_eq!(cpu.reg.de(), 0xFF56);
+        assert_eq!(cpu.reg.hl(), 0x000D);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_strict_access_blocks_vram_while_drawing() {
+        let mut cpu = CPU::new();
+        // LD A,(0x8000); LD (0x8001),A
+        load_program(&mut cpu, &[0xFA, 0x00, 0x80, 0xEA, 0x01, 0x80]);
+        cpu.memory_mut().write_byte_forced(0x8000, 0x42);
+        cpu.memory_mut().write_byte_forced(LCD_CONTROL, 0x80);
+        cpu.set_memory_access(MemoryAccess::Strict);
+
+        // Park the PPU in the middle of mode 3 for the whole instruction
+        cpu.mmu.screen = Screen::new();
+        for _ in 0..22 {
+            cpu.mmu.tick();
+        }
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.a, 0xFF);
+
+        cpu.set_memory_access(MemoryAccess::Permissive);
+        cpu.reg.pc = 0x100;
+        cpu.execute_next_opcode(false);
+        assert_eq!(cpu.reg.a, 0x42);
     }
 
-    fn is_clock_enabled(mem: &Memory) -> bool {
-        let tmc_reg = mem.read_byte(TMC);
-        tmc_reg & 0x4 != 0
+    #[test]
+    #[timeout(100)]
+    fn test_trace_callback() {
+        // The callback keeps its own count rather than needing a static
+        let traced = Rc::new(Cell::new(0));
+        let count = traced.clone();
+        let mut cpu = CPU::new();
+        load_program(&mut cpu, &[0x3C]);
+        cpu.set_trace(Trace {
+            instructions: true,
+            interrupts: true,
+            callback: Some(Box::new(move |event: &TraceEvent| match event {
+                TraceEvent::Instruction {
+                    pc: 0x100,
+                    opcode: 0x3C,
+                    ..
+                } => count.set(count.get() + 1),
+                TraceEvent::Interrupt { vector: 0x40 } => count.set(count.get() + 0x100),
+                _ => {}
+            })),
+        });
+
+        cpu.execute_next_opcode(false);
+        assert_eq!(traced.get(), 1);
+
+        cpu.ime = true;
+        request_vblank(&mut cpu);
+        cpu.execute_next_opcode(false);
+        assert_eq!(traced.get(), 0x101);
     }
 }
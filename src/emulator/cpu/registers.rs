@@ -1,3 +1,4 @@
+use crate::emulator::model::Model;
 use crate::types::{Byte, Word};
 
 #[derive(Copy, Clone)]
@@ -37,8 +38,58 @@ impl Registers {
         }
     }
 
-    pub fn reset(&mut self) {
-        *self = Registers::new();
+    /// Register values at power on, before a boot ROM has run
+    pub fn power_on() -> Self {
+        Registers {
+            a: 0x00,
+            f: 0x00,
+            b: 0x00,
+            c: 0x00,
+            d: 0x00,
+            e: 0x00,
+            h: 0x00,
+            l: 0x00,
+            sp: 0x0000,
+            pc: 0x0000,
+        }
+    }
+
+    /// Register values left behind by the boot ROM of the given hardware
+    ///
+    /// The DMG and MGB boot ROMs leave H and C set unless the header checksum
+    /// is zero, and in DMG compatibility mode the CGB boot ROM leaves the title
+    /// checksum of Nintendo games in B.
+    pub fn for_model(
+        model: Model,
+        cgb_mode: bool,
+        header_checksum: Byte,
+        title_checksum: Option<Byte>,
+    ) -> Self {
+        let dmg_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        let (af, bc, de, hl) = match model {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x0100 | dmg_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF00 | dmg_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::Cgb if cgb_mode => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::Cgb => {
+                let b = title_checksum.unwrap_or(0);
+                let hl = if b == 0x43 || b == 0x58 {
+                    0x991A
+                } else {
+                    0x007C
+                };
+                (0x1180, (b as Word) << 8, 0x0008, hl)
+            }
+        };
+
+        let mut registers = Registers::new();
+        registers.setaf(af);
+        registers.setbc(bc);
+        registers.setde(de);
+        registers.sethl(hl);
+        registers
     }
 
     pub fn af(&self) -> Word {
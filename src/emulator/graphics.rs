@@ -1,25 +1,75 @@
+use crate::emulator::config::PixelFormat;
 use crate::emulator::mem::*;
+use crate::emulator::palette::{Palette, Shades};
 use crate::types::*;
 
 /// Basic implementation and methods for the LCD Screen
 pub struct Screen {
     scanline_counter: i32,
 
-    //buffer is of size (h * w * 3)
-    //buffer can be indexed as (h + (w*3))
-    pub buffer: LCD, // Each pixel is a byte (0-3 for Game Boy palettes)
+    //buffer holds h * w pixels of the current format, only the start is used
+    //for formats narrower than 4 bytes
+    //buffer can be indexed as ((y * w) + x) * bytes_per_pixel
+    pub buffer: LCD,
+    format: PixelFormat,
+
+    /// Background color number of each pixel on the current line, for sprite priority
+    bg_colors: [Byte; SCREEN_WIDTH as usize],
+    /// CGB background tiles on the current line that are drawn over sprites
+    bg_priority: [bool; SCREEN_WIDTH as usize],
+    palette: Palette,
+    /// Set when the PPU enters VBlank, cleared by `take_frame`
+    frame_ready: bool,
 }
 
 impl Screen {
     pub fn new() -> Self {
         // TODO: Initialize the screen buffer
         Screen {
-            buffer: [0; (SCREEN_HEIGHT * SCREEN_WIDTH * 3) as usize],
+            buffer: [0; (SCREEN_HEIGHT * SCREEN_WIDTH * 4) as usize],
+            format: PixelFormat::default(),
 
             scanline_counter: 456,
+            bg_colors: [0; SCREEN_WIDTH as usize],
+            bg_priority: [false; SCREEN_WIDTH as usize],
+            palette: Palette::default(),
+            frame_ready: false,
         }
     }
 
+    /// Back to the power on state, keeping the palette and pixel format
+    pub fn reset(&mut self) {
+        *self = Screen {
+            palette: self.palette,
+            format: self.format,
+            ..Screen::new()
+        };
+    }
+
+    /// Sets the colors used for the DMG shades from the next line on
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Sets the layout pixels are written in from the next line on
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.format = format;
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// The finished pixels, sized for the current format
+    pub fn buffer(&self) -> &[Byte] {
+        let len = (SCREEN_HEIGHT * SCREEN_WIDTH) as usize * self.format.bytes_per_pixel();
+        &self.buffer[..len]
+    }
+
     pub fn update_screen(&mut self, mem: &mut Memory, cycles: i32) {
         self.set_lcd_status(mem);
 
@@ -40,6 +90,7 @@ impl Screen {
             // we are now in the vertical blank period
             if scanline == 144 {
                 mem.request_interrupt(0);
+                self.frame_ready = true;
             }
             // If we are past 153, we reset to 0
             else if scanline > 153 {
@@ -52,11 +103,28 @@ impl Screen {
         }
     }
 
+    /// Whether a frame was finished since the last call
+    pub fn take_frame(&mut self) -> bool {
+        core::mem::take(&mut self.frame_ready)
+    }
+
+    /// Blanks the LCD to white, used while the system is stopped or the LCD is off
+    pub fn blank(&mut self) {
+        match self.format {
+            PixelFormat::ShadeIndex => self.buffer.fill(0),
+            _ => self.buffer.fill(0xFF),
+        }
+    }
+
     fn draw_scanline(&mut self, mem: &Memory) {
         let control = mem.read_byte(LCD_CONTROL);
 
         if control & (1 << 7) != 0 {
-            if control & 0x1 != 0 {
+            self.bg_colors = [0; SCREEN_WIDTH as usize];
+            self.bg_priority = [false; SCREEN_WIDTH as usize];
+
+            // On the CGB bit 0 is the sprite master priority instead of a background enable
+            if control & 0x1 != 0 || mem.cgb_mode() {
                 self.render_tiles(mem, control);
             }
 
@@ -67,6 +135,7 @@ impl Screen {
     }
 
     fn render_tiles(&mut self, mem: &Memory, control: Byte) {
+        let cgb = mem.cgb_mode();
         let mut unsigned = true;
         let tile_data = if control & (1 << 4) != 0 {
             0x8000
@@ -111,7 +180,10 @@ impl Screen {
             }
 
             let tile_column = (x_pos / 8) as Word;
-            let tile_num = mem.read_byte(background_memory + tile_row + tile_column);
+            let map_addr = background_memory + tile_row + tile_column;
+            let tile_num = mem.read_vram(0, map_addr);
+            // The CGB keeps the attributes of each tile at the same spot in VRAM bank 1
+            let attributes = if cgb { mem.read_vram(1, map_addr) } else { 0 };
 
             let mut tile_location: Word = tile_data;
             if unsigned {
@@ -121,19 +193,28 @@ impl Screen {
                 tile_location += ((signed + 128) as Word) * 16;
             }
 
-            let line = (y_pos % 8) * 2;
-            let data1 = mem.read_byte(tile_location + line as Word);
-            let data2 = mem.read_byte(tile_location + line as Word + 1);
+            let mut line = y_pos % 8;
+            if attributes & (1 << 6) != 0 {
+                line = 7 - line;
+            }
+            let line = line as Word * 2;
+            let bank = ((attributes >> 3) & 1) as usize;
+            let data1 = mem.read_vram(bank, tile_location + line);
+            let data2 = mem.read_vram(bank, tile_location + line + 1);
 
-            let color_bit = 7 - (x_pos % 8);
+            let color_bit = if attributes & (1 << 5) != 0 {
+                x_pos % 8
+            } else {
+                7 - (x_pos % 8)
+            };
             let color_num = (((data2 >> color_bit) & 1) << 1) | ((data1 >> color_bit) & 1);
 
-            let color: Color = mem.get_color(color_num, 0xFF47).unwrap_or(Color::Black);
-            let (red, green, blue) = match color {
-                Color::White => (255, 255, 255),
-                Color::LightGrey => (0xCC, 0xCC, 0xCC),
-                Color::DarkGrey => (0x77, 0x77, 0x77),
-                Color::Black => (0, 0, 0),
+            let (rgb, shade) = if cgb {
+                let rgb = cgb_rgb(mem.bg_palette_color(attributes & 0x7, color_num));
+                (rgb, color_num)
+            } else {
+                let color = mem.get_color(color_num, 0xFF47).unwrap_or(Color::Black);
+                (dmg_rgb(&self.palette.bg, color), color as Byte)
             };
 
             if current_line as usize >= SCREEN_HEIGHT as usize
@@ -142,125 +223,138 @@ impl Screen {
                 continue;
             }
 
-            let idx = (current_line as usize * SCREEN_WIDTH as usize + pixel as usize) * 3;
-            self.buffer[idx] = red;
-            self.buffer[idx + 1] = green;
-            self.buffer[idx + 2] = blue;
+            self.bg_colors[pixel as usize] = color_num;
+            self.bg_priority[pixel as usize] = attributes & (1 << 7) != 0;
+            self.set_pixel(current_line as usize, pixel as usize, rgb, shade);
         }
     }
 
     fn render_sprites(&mut self, mem: &Memory, control: Byte) {
-        // test if display is enabled
-        if control & 0x2 != 0 {
-            let mut use8x16 = false;
-            if control & 0x4 != 0 {
-                use8x16 = true;
+        let cgb = mem.cgb_mode();
+        let y_size = if control & 0x4 != 0 { 16 } else { 8 };
+        let scanline = mem.read_byte(CURRENT_SCANLINE) as i32;
+
+        // Only the first 10 sprites on the line in OAM order are drawn
+        let mut sprites: [Word; 10] = [0; 10];
+        let mut count = 0;
+        for sprite in 0..40 {
+            let y_pos = mem.read_byte(SPRITE_RAM + sprite * 4) as i32 - 16;
+            if (scanline >= y_pos) && (scanline < (y_pos + y_size)) {
+                sprites[count] = sprite;
+                count += 1;
+                if count == sprites.len() {
+                    break;
+                }
             }
+        }
+        let sprites = &mut sprites[..count];
 
-            for sprite in 0..40 {
-                let index = sprite * 4;
-                let y_pos = mem.read_byte(0xFE00 + index) as i32 - 16;
-                let x_pos = mem.read_byte(0xFE00 + index + 1) as i32 - 8;
-                let tile_location = mem.read_byte(0xFE00 + index + 2);
-                let attributes = mem.read_byte(0xFE00 + index + 3);
+        // The DMG favours the leftmost sprite, the CGB only goes by OAM order
+        if !cgb {
+            sprites.sort_unstable_by_key(|&sprite| {
+                (mem.read_byte(SPRITE_RAM + sprite * 4 + 1), sprite)
+            });
+        }
 
-                let y_flip = attributes & (1 << 6) != 0;
-                let x_flip = attributes & (1 << 5) != 0;
+        // Pixels already claimed by a higher priority sprite
+        let mut drawn = [false; SCREEN_WIDTH as usize];
 
-                let scanline = mem.read_byte(CURRENT_SCANLINE) as i32;
-                let mut y_size = 8;
+        for &sprite in sprites.iter() {
+            let index = SPRITE_RAM + sprite * 4;
+            let y_pos = mem.read_byte(index) as i32 - 16;
+            let x_pos = mem.read_byte(index + 1) as i32 - 8;
+            let mut tile_location = mem.read_byte(index + 2);
+            let attributes = mem.read_byte(index + 3);
 
-                if use8x16 {
-                    y_size = 16;
-                }
+            if y_size == 16 {
+                tile_location &= 0xFE;
+            }
+
+            let mut line = scanline - y_pos;
+            if attributes & (1 << 6) != 0 {
+                line = y_size - 1 - line;
+            }
 
-                if (scanline >= y_pos) && (scanline < (y_pos + y_size)) {
-                    let mut line = scanline - y_pos;
-
-                    if y_flip {
-                        line -= y_size;
-                        line *= -1;
-                    }
-
-                    line *= 2;
-                    let data1 =
-                        mem.read_byte((0x8000 + (tile_location as Word * 16)) + line as Word);
-                    let data2 =
-                        mem.read_byte((0x8000 + (tile_location as Word * 16)) + line as Word + 1);
-
-                    for tile_pixel in (0..=7).rev() {
-                        let mut color_bit = tile_pixel;
-
-                        if x_flip {
-                            color_bit -= 7;
-                            color_bit *= -1;
-                        }
-                        let color_num = (((data2 & (1 << color_bit)) >> color_bit) << 1)
-                            | ((data1 & (1 << color_bit)) >> color_bit);
-
-                        let addr = match attributes & (1 << 4) != 0 {
-                            true => 0xFF49,
-                            false => 0xFF48,
-                        };
-
-                        let color = mem.get_color(color_num, addr).unwrap_or(Color::Black);
-
-                        if color == Color::White {
-                            continue;
-                        }
-
-                        let red;
-                        let blue;
-                        let green;
-
-                        match color {
-                            Color::White => {
-                                red = 255;
-                                green = 255;
-                                blue = 255;
-                            }
-                            Color::LightGrey => {
-                                red = 0xCC;
-                                green = 0xCC;
-                                blue = 0xCC;
-                            }
-                            Color::DarkGrey => {
-                                red = 0x77;
-                                green = 0x77;
-                                blue = 0x77;
-                            }
-                            Color::Black => {
-                                red = 0;
-                                green = 0;
-                                blue = 0;
-                            }
-                        }
-
-                        let x_pix = 7 - tile_pixel;
-                        let pixel = x_pos + x_pix;
-
-                        if pixel < 0
-                            || pixel >= SCREEN_WIDTH as i32
-                            || scanline < 0
-                            || scanline >= SCREEN_HEIGHT as i32
-                        {
-                            continue;
-                        }
-
-                        if attributes & (1 << 7) != 0 {
-                            continue; // TODO: Add packground handling
-                        }
-
-                        let idx = (scanline as usize * SCREEN_WIDTH as usize + pixel as usize) * 3;
-                        self.buffer[idx] = red;
-                        self.buffer[idx + 1] = green;
-                        self.buffer[idx + 2] = blue;
-                    }
+            let bank = if cgb {
+                ((attributes >> 3) & 1) as usize
+            } else {
+                0
+            };
+            let addr = 0x8000 + (tile_location as Word * 16) + (line * 2) as Word;
+            let data1 = mem.read_vram(bank, addr);
+            let data2 = mem.read_vram(bank, addr + 1);
+
+            for x_pix in 0..8 {
+                let pixel = x_pos + x_pix;
+                if pixel < 0 || pixel >= SCREEN_WIDTH as i32 || drawn[pixel as usize] {
+                    continue;
+                }
+                let pixel = pixel as usize;
+
+                let color_bit = if attributes & (1 << 5) != 0 {
+                    x_pix
+                } else {
+                    7 - x_pix
+                };
+                let color_num = (((data2 >> color_bit) & 1) << 1) | ((data1 >> color_bit) & 1);
+
+                // Color 0 is transparent and lets lower priority sprites through
+                if color_num == 0 {
+                    continue;
                 }
+                drawn[pixel] = true;
+
+                let behind_background = if cgb {
+                    control & 0x1 != 0 && (attributes & (1 << 7) != 0 || self.bg_priority[pixel])
+                } else {
+                    attributes & (1 << 7) != 0
+                };
+                if behind_background && self.bg_colors[pixel] != 0 {
+                    continue;
+                }
+
+                let (rgb, shade) = if cgb {
+                    let rgb = cgb_rgb(mem.obj_palette_color(attributes & 0x7, color_num));
+                    (rgb, color_num)
+                } else {
+                    let (addr, shades) = match attributes & (1 << 4) != 0 {
+                        true => (0xFF49, &self.palette.obj1),
+                        false => (0xFF48, &self.palette.obj0),
+                    };
+                    let color = mem.get_color(color_num, addr).unwrap_or(Color::Black);
+                    (dmg_rgb(shades, color), color as Byte)
+                };
+
+                self.set_pixel(scanline as usize, pixel, rgb, shade);
             }
         }
     }
 
+    /// Writes one pixel in the current format, `shade` is the DMG shade or
+    /// the CGB color number within its palette
+    fn set_pixel(
+        &mut self,
+        line: usize,
+        pixel: usize,
+        (red, green, blue): (Byte, Byte, Byte),
+        shade: Byte,
+    ) {
+        let size = self.format.bytes_per_pixel();
+        let idx = (line * SCREEN_WIDTH as usize + pixel) * size;
+        let out = &mut self.buffer[idx..idx + size];
+        match self.format {
+            PixelFormat::Rgb888 => out.copy_from_slice(&[red, green, blue]),
+            PixelFormat::Rgba8888 => out.copy_from_slice(&[red, green, blue, 0xFF]),
+            PixelFormat::Bgra8888 => out.copy_from_slice(&[blue, green, red, 0xFF]),
+            PixelFormat::Rgb565 => {
+                let color =
+                    ((red as Word >> 3) << 11) | ((green as Word >> 2) << 5) | (blue as Word >> 3);
+                out.copy_from_slice(&color.to_le_bytes());
+            }
+            PixelFormat::ShadeIndex => out[0] = shade,
+        }
+    }
+
     fn set_lcd_status(&mut self, mem: &mut Memory) {
         let lcd_enabled = self.is_lcd_enabled(mem);
 
@@ -315,6 +409,11 @@ impl Screen {
             mem.request_interrupt(1);
         }
 
+        // HBlank is when the CGB copies the next HDMA block
+        if mode == 0 && mode != current_mode {
+            mem.hblank();
+        }
+
         // check the conicidence flag
         if current_line == mem.read_byte(COINCIDENCE_FLAG) {
             status |= 0x4;
@@ -329,7 +428,7 @@ impl Screen {
         mem.write_byte(LCD_STATUS, status);
     }
 
-    fn is_lcd_enabled(&self, mem: &Memory) -> bool {
+    pub fn is_lcd_enabled(&self, mem: &Memory) -> bool {
         // Check bit 7 of LCD Control register (0xFF40)
         mem.read_byte(LCD_CONTROL) & (1 << 7) != 0
     }
@@ -337,9 +436,25 @@ impl Screen {
     // TODO: Add more methods for drawing, sprites, etc.
 }
 
+/// RGB values for one of the DMG shades
+fn dmg_rgb(shades: &Shades, color: Color) -> (Byte, Byte, Byte) {
+    let [red, green, blue] = shades[color as usize];
+    (red, green, blue)
+}
+
+/// Expands a 15-bit CGB color to 24-bit RGB
+fn cgb_rgb(color: Word) -> (Byte, Byte, Byte) {
+    let expand = |value: Word| {
+        let value = (value & 0x1F) as Byte;
+        (value << 3) | (value >> 2)
+    };
+    (expand(color), expand(color >> 5), expand(color >> 10))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::emulator::model::Model;
     use ntest::timeout;
 
     #[test]
@@ -377,4 +492,137 @@ mod test {
         assert_eq!(screen.buffer[correct], 255);
         assert_eq!(screen.buffer[1], 0);
     }
+
+    fn pixel(screen: &Screen, line: usize, pixel: usize) -> (Byte, Byte, Byte) {
+        let idx = (line * SCREEN_WIDTH as usize + pixel) * 3;
+        (
+            screen.buffer[idx],
+            screen.buffer[idx + 1],
+            screen.buffer[idx + 2],
+        )
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_sprite_behind_background() {
+        let mut mem = Memory::new();
+        mem.ram_startup();
+        let mut screen = Screen::new();
+
+        mem.write_byte_forced(LCD_CONTROL, 0x93);
+        mem.write_byte_forced(CURRENT_SCANLINE, 0);
+        mem.write_byte_forced(0xFF47, 0xE4);
+        mem.write_byte_forced(0xFF48, 0xE4);
+        // Background tile 0 has color 1 on the left half and color 0 on the right
+        mem.write_byte_forced(0x8000, 0xF0);
+        mem.write_byte_forced(0x8010, 0xFF);
+        mem.write_byte_forced(0x8011, 0xFF);
+        // Sprite using tile 1 at the top left that sits behind the background
+        mem.write_byte_forced(0xFE00, 16);
+        mem.write_byte_forced(0xFE01, 8);
+        mem.write_byte_forced(0xFE02, 1);
+        mem.write_byte_forced(0xFE03, 0x80);
+
+        screen.draw_scanline(&mem);
+
+        assert_eq!(pixel(&screen, 0, 0), (0xCC, 0xCC, 0xCC));
+        assert_eq!(pixel(&screen, 0, 4), (0, 0, 0));
+        assert_eq!(pixel(&screen, 0, 8), (0xCC, 0xCC, 0xCC));
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_cgb_tile_attributes() {
+        let mut mem = Memory::new();
+        mem.set_model(Model::Cgb);
+        let mut rom = [0; 0x8000];
+        rom[0x143] = 0xC0;
+        mem.load_rom_data(&rom);
+        mem.ram_startup();
+        let mut screen = Screen::new();
+
+        mem.write_byte_forced(CURRENT_SCANLINE, 0);
+        // Palette 2 color 1 is pure red
+        mem.write_byte(BCPS, 0x80 | 0x12);
+        mem.write_byte(BCPD, 0x1F);
+        mem.write_byte(BCPD, 0x00);
+        // The last row of tile 0 in bank 1 shows up on line 0 when flipped
+        mem.write_byte(VBK, 1);
+        mem.write_byte(0x800E, 0x80);
+        mem.write_byte(0x9800, 0x4A); // y flip, bank 1, palette 2
+        mem.write_byte(VBK, 0);
+
+        screen.render_tiles(&mem, 0x91);
+
+        assert_eq!(pixel(&screen, 0, 0), (0xFF, 0, 0));
+        assert_eq!(pixel(&screen, 0, 1), (0xFF, 0xFF, 0xFF));
+        // Tile 1 uses palette 0 and bank 0 which is empty
+        assert_eq!(pixel(&screen, 0, 8), (0xFF, 0xFF, 0xFF));
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_custom_palette() {
+        let mut mem = Memory::new();
+        mem.ram_startup();
+        let mut screen = Screen::new();
+        let sprites = [[0xFF, 0, 0]; 4];
+        screen.set_palette(Palette::new(
+            [
+                [0xE0, 0xF8, 0xD0],
+                [0x88, 0xC0, 0x70],
+                [0x34, 0x68, 0x56],
+                [0x08, 0x18, 0x20],
+            ],
+            sprites,
+            sprites,
+        ));
+
+        mem.write_byte_forced(CURRENT_SCANLINE, 0);
+        mem.write_byte_forced(0xFF47, 0xE4);
+        mem.write_byte_forced(0x9800, 0x01);
+        mem.write_byte_forced(0x8010, 0x80);
+        mem.write_byte_forced(0x8011, 0x80);
+
+        screen.render_tiles(&mem, 0x91);
+
+        assert_eq!(pixel(&screen, 0, 0), (0x08, 0x18, 0x20));
+        assert_eq!(pixel(&screen, 0, 1), (0xE0, 0xF8, 0xD0));
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_pixel_formats() {
+        let mut mem = Memory::new();
+        mem.ram_startup();
+        let mut screen = Screen::new();
+
+        mem.write_byte_forced(CURRENT_SCANLINE, 0);
+        mem.write_byte_forced(0xFF47, 0xE4);
+        // Pixel 0 is color 2, the rest of the line color 0
+        mem.write_byte_forced(0x8001, 0x80);
+
+        let formats = [
+            (PixelFormat::Rgba8888, [0x77, 0x77, 0x77, 0xFF]),
+            (PixelFormat::Bgra8888, [0x77, 0x77, 0x77, 0xFF]),
+            (PixelFormat::Rgb565, [0xAE, 0x73, 0, 0]),
+            (PixelFormat::ShadeIndex, [2, 0, 0, 0]),
+        ];
+        for (format, expected) in formats {
+            screen.set_pixel_format(format);
+            screen.render_tiles(&mem, 0x91);
+            let size = format.bytes_per_pixel();
+            assert_eq!(screen.buffer().len(), 160 * 144 * size);
+            assert_eq!(screen.buffer()[..size], expected[..size]);
+        }
+
+        // Red and blue swap places in BGRA
+        screen.set_palette(Palette::uniform([[0xFF, 0, 0]; 4]));
+        screen.set_pixel_format(PixelFormat::Bgra8888);
+        screen.render_tiles(&mem, 0x91);
+        assert_eq!(screen.buffer()[..4], [0, 0, 0xFF, 0xFF]);
+        screen.set_pixel_format(PixelFormat::Rgb565);
+        screen.render_tiles(&mem, 0x91);
+        assert_eq!(screen.buffer()[..2], [0x00, 0xF8]);
+    }
 }
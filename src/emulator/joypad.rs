@@ -43,6 +43,45 @@ impl Joypad {
         self.write_input_to_mem(mem);
     }
 
+    /// Buttons held down, one bit each in the order A, B, Select, Start,
+    /// Right, Left, Up, Down from bit 0
+    pub fn state(&self) -> u8 {
+        let keys = [
+            self.a,
+            self.b,
+            self.select,
+            self.start,
+            self.right,
+            self.left,
+            self.up,
+            self.down,
+        ];
+        keys.iter()
+            .enumerate()
+            .fold(0, |state, (bit, key)| match key {
+                KeyState::Pressed => state | (1 << bit),
+                KeyState::Released => state,
+            })
+    }
+
+    /// Sets every button at once from a value returned by `state`
+    pub fn set_state(&mut self, mem: &mut Memory, state: u8) {
+        let key = |bit: u8| match state & (1 << bit) != 0 {
+            true => KeyState::Pressed,
+            false => KeyState::Released,
+        };
+        self.a = key(0);
+        self.b = key(1);
+        self.select = key(2);
+        self.start = key(3);
+        self.right = key(4);
+        self.left = key(5);
+        self.up = key(6);
+        self.down = key(7);
+
+        self.write_input_to_mem(mem);
+    }
+
     /// Main hardworking function that does the work to write the joypad state to RAM
     fn write_input_to_mem(&mut self, mem: &mut Memory) {
         let mut buttons = 0;
@@ -108,6 +147,20 @@ mod tests {
         assert_eq!(if_val & (1 << 4), 0);
     }
 
+    #[test]
+    fn state_round_trips() {
+        let mut mem = Memory::new();
+        let mut joypad = setup_joypad(&mut mem, 0x20); // select directions
+        joypad.log_input(&mut mem, GameInput::Start, KeyState::Pressed);
+        joypad.log_input(&mut mem, GameInput::Left, KeyState::Pressed);
+        assert_eq!(joypad.state(), 0x28);
+
+        let mut other = Joypad::new();
+        other.set_state(&mut mem, 0x28);
+        assert_eq!(other.state(), 0x28);
+        assert_eq!(mem.read_byte_forced(INPUT_REGISTER), 0xED);
+    }
+
     #[test]
     fn release_does_not_request_interrupt() {
         let mut mem = Memory::new();
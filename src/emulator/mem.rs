@@ -1,8 +1,77 @@
 use core::ops::BitAnd;
 
 /// Functions and storage for operating on device memory
+use crate::emulator::cheats::Cheats;
+use crate::emulator::model::Model;
+use crate::emulator::serial::Serial;
+use crate::emulator::timer::Timer;
 use crate::types::*;
 
+/// An OAM DMA in progress, copying one byte per M-cycle
+#[derive(Clone, Copy)]
+struct Dma {
+    source: Word,
+    index: Word,
+    /// M-cycles left before the first byte is copied
+    delay: u8,
+}
+
+/// CGB VRAM DMA registers, copying 16 byte blocks into VRAM
+#[derive(Clone, Copy)]
+struct Hdma {
+    source: Word,
+    dest: Word,
+    /// Blocks left to copy, minus one, as read back from HDMA5
+    remaining: Byte,
+    /// A copy of one block per HBlank is in progress
+    active: bool,
+}
+
+impl Hdma {
+    fn new() -> Self {
+        Hdma {
+            source: 0,
+            dest: 0,
+            remaining: 0x7F,
+            active: false,
+        }
+    }
+}
+
+/// A part of memory that can be accessed bank by bank
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegion {
+    /// Cartridge ROM in 16 KiB banks, bank 0 is mapped at 0x0000
+    Rom,
+    /// Cartridge RAM in 8 KiB banks mapped at 0xA000
+    CartRam,
+    /// Video RAM in 8 KiB banks mapped at 0x8000, two on the CGB
+    Vram,
+    /// Work RAM in 4 KiB banks, bank 0 is mapped at 0xC000 and the rest at
+    /// 0xD000, eight on the CGB
+    Wram,
+    /// Sprite attributes at 0xFE00, 160 bytes
+    Oam,
+    /// I/O registers at 0xFF00, 128 bytes
+    Io,
+    /// High RAM at 0xFF80, 127 bytes
+    Hram,
+}
+
+impl MemoryRegion {
+    /// Bytes in each bank of the region
+    pub const fn bank_size(self) -> usize {
+        match self {
+            MemoryRegion::Rom => 0x4000,
+            MemoryRegion::CartRam | MemoryRegion::Vram => 0x2000,
+            MemoryRegion::Wram => 0x1000,
+            MemoryRegion::Oam => 0xA0,
+            MemoryRegion::Io => 0x80,
+            MemoryRegion::Hram => 0x7F,
+        }
+    }
+}
+
 pub struct Memory {
     mem: Ram,
     rom: [Byte; MAX_ROM_SIZE],
@@ -15,8 +84,31 @@ pub struct Memory {
     rom_bank_enable: bool,
     joypad_buttons: Byte,
     joypad_directions: Byte,
-
-    pub timer_counter: i32,
+    timer: Timer,
+    serial: Serial,
+    dma: Option<Dma>,
+    model: Model,
+    /// CGB features are only turned on for cartridges that support them
+    cgb_mode: bool,
+    vram_bank: usize,
+    vram_bank1: [Byte; 0x2000],
+    /// Bank mapped at 0xD000, bank 1 lives in `mem` and banks 2-7 in `wram_banks`
+    wram_bank: usize,
+    wram_banks: [[Byte; 0x1000]; 6],
+    bg_palettes: [Byte; 64],
+    obj_palettes: [Byte; 64],
+    double_speed: bool,
+    speed_switch_armed: bool,
+    hdma: Hdma,
+    /// M-cycles the CPU has to wait for a VRAM DMA
+    dma_stall: u32,
+    boot_rom: [Byte; BOOT_ROM_SIZE_CGB],
+    /// Zero when no boot ROM was supplied
+    boot_rom_len: usize,
+    /// The boot ROM covers the cartridge until 0xFF50 is written
+    boot_rom_mapped: bool,
+    /// Game Genie codes patch ROM reads, GameShark codes are applied by the emulator
+    cheats: Cheats,
 }
 
 impl Default for Memory {
@@ -39,9 +131,85 @@ impl Memory {
             rom_bank_enable: true,
             joypad_buttons: 0x0F,
             joypad_directions: 0x0F,
+            timer: Timer::new(),
+            serial: Serial::new(),
+            dma: None,
+            model: Model::Dmg,
+            cgb_mode: false,
+            vram_bank: 0,
+            vram_bank1: [0; 0x2000],
+            wram_bank: 1,
+            wram_banks: [[0; 0x1000]; 6],
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0; 64],
+            double_speed: false,
+            speed_switch_armed: false,
+            hdma: Hdma::new(),
+            dma_stall: 0,
+            boot_rom: [0; BOOT_ROM_SIZE_CGB],
+            boot_rom_len: 0,
+            boot_rom_mapped: false,
+            cheats: Cheats::new(),
+        }
+    }
+
+    /// The loaded cartridge ROM
+    pub fn rom(&self) -> &[Byte] {
+        &self.rom[..self.rom_len]
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
 
-            timer_counter: 1024,
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn serial(&self) -> &Serial {
+        &self.serial
+    }
+
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    /// Sets the boot ROM run by the next ROM load, an empty image removes it
+    ///
+    /// Images must be 256 bytes (DMG/MGB) or 2304 bytes (CGB)
+    pub fn set_boot_rom(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        if !matches!(data.len(), 0 | BOOT_ROM_SIZE | BOOT_ROM_SIZE_CGB) {
+            return Err("boot ROM must be 256 or 2304 bytes");
         }
+        self.boot_rom.fill(0);
+        self.boot_rom[..data.len()].copy_from_slice(data);
+        self.boot_rom_len = data.len();
+        Ok(())
+    }
+
+    /// Whether the boot ROM is still mapped over the cartridge
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    /// The hardware model this memory belongs to
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Sets the hardware model, taking effect on the next ROM load
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// Whether the CGB only registers and banks are enabled
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    /// Whether the CPU is running in CGB double speed mode
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
     }
 
     // Wrapper for memory read functionality
@@ -57,6 +225,10 @@ impl Memory {
         if addr < 0x8000 {
             self.handle_banking(addr, value);
         }
+        // VRAM bank 1 is only selectable in CGB mode
+        else if (0x8000..0xA000).contains(&addr) && self.vram_bank == 1 {
+            self.vram_bank1[(addr - 0x8000) as usize] = value;
+        }
         //inserts value into the ram banks if enabled
         else if (0xA000..0xC000).contains(&addr) {
             if self.ram_write_enable {
@@ -64,6 +236,8 @@ impl Memory {
                 let bank = self.ram_banks as usize;
                 self.external_ram[bank][offset] = value;
             }
+        } else if (0xD000..0xE000).contains(&addr) && self.wram_bank > 1 {
+            self.wram_banks[self.wram_bank - 2][(addr - 0xD000) as usize] = value;
         }
         // echo ram writes to two locations
         else if (0xE000..0xFE00).contains(&addr) {
@@ -73,17 +247,11 @@ impl Memory {
         // restricted memory area
         else if (0xFEA0..0xFEFF).contains(&addr) {
             //TODO: implement error handling here (likely throw some kind of interrupt)
-        } else if TMC == addr {
-            let current_frequency: Byte = self.get_clock_freq();
-            self.mem[TMC as usize] = value;
-            let new_frequency = self.get_clock_freq();
-
-            if current_frequency != new_frequency {
-                self.set_clock_frequency();
-            }
-        } else if DIVIDER_REGISTER == addr {
-            // If ever writing to the divider register always set it to 0
-            self.mem[DIVIDER_REGISTER as usize] = 0;
+        } else if (DIVIDER_REGISTER..=TMC).contains(&addr) {
+            // DIV, TIMA, TMA and TAC all have side effects on the timer
+            self.timer.write(addr, value);
+        } else if (SERIAL_DATA..=SERIAL_CONTROL).contains(&addr) {
+            self.serial.write(addr, value);
         } else if addr == CURRENT_SCANLINE {
             // If ever writing to the current scanline always set it to 0
             self.mem[CURRENT_SCANLINE as usize] = 0;
@@ -94,6 +262,18 @@ impl Memory {
         } else if addr == DMA_REG {
             // Game is activating a direct memory access
             self.dma_transfer(value);
+        } else if addr == BOOT_REG {
+            // Once the boot ROM is gone it can't be mapped back in
+            if self.boot_rom_mapped && value != 0 {
+                self.unmap_boot_rom();
+            }
+        } else if addr == KEY0 {
+            // Locked once the boot ROM is done
+            if self.boot_rom_mapped {
+                self.mem[KEY0 as usize] = value;
+            }
+        } else if self.cgb_mode && is_cgb_register(addr) {
+            self.write_cgb_register(addr, value);
         } else {
             self.mem[addr as usize] = value;
         }
@@ -111,15 +291,26 @@ impl Memory {
                     self.rom_len = index + 1;
                 }
             }
+        } else if (0x8000..0xA000).contains(&addr) && self.vram_bank == 1 {
+            self.vram_bank1[(addr - 0x8000) as usize] = value;
+            return;
         } else if (0xA000..=0xBFFF).contains(&addr) {
             let offset = (addr - 0xA000) as usize;
             let bank = self.ram_banks as usize;
             self.external_ram[bank][offset] = value;
+        } else if (0xD000..0xE000).contains(&addr) && self.wram_bank > 1 {
+            self.wram_banks[self.wram_bank - 2][(addr - 0xD000) as usize] = value;
+            return;
+        } else if (DIVIDER_REGISTER..=TMC).contains(&addr) {
+            self.timer.write_forced(addr, value);
+        } else if (SERIAL_DATA..=SERIAL_CONTROL).contains(&addr) {
+            self.serial.write_forced(addr, value);
         }
         //Sets byte
         self.mem[addr as usize] = value;
     }
 
+    #[allow(dead_code)]
     /// Function to write a word
     pub fn write_word(&mut self, addr: Word, value: Word) {
         let l = value & 0xff;
@@ -128,6 +319,7 @@ impl Memory {
         self.write_byte(addr.wrapping_add(1), h as Byte);
     }
 
+    #[allow(dead_code)]
     /// Function to read a word
     pub fn read_word(&self, addr: Word) -> Word {
         let l = self.read_byte(addr) as Word;
@@ -142,6 +334,155 @@ impl Memory {
         self.read_byte_internal(addr)
     }
 
+    /// Writes a byte wherever the CPU would see it without any side effects
+    ///
+    /// ROM is patched in whichever bank is mapped, cartridge RAM is written even
+    /// when disabled, and registers are set without starting DMA, serial
+    /// transfers, joypad interrupts or resetting DIV.
+    pub fn poke(&mut self, addr: Word, value: Byte) {
+        if self.boot_rom_mapped && self.in_boot_rom(addr) {
+            self.boot_rom[addr as usize] = value;
+            return;
+        }
+        if let Some((region, bank, offset)) = self.locate(addr) {
+            if let Some(data) = self.bank_mut(region, bank) {
+                data[offset] = value;
+            }
+            return;
+        }
+
+        if (0xE000..0xFE00).contains(&addr) {
+            self.mem[addr as usize] = value;
+            self.poke(addr - 0x2000, value);
+        } else if (DIVIDER_REGISTER..=TMC).contains(&addr) {
+            self.timer.write_forced(addr, value);
+        } else if (SERIAL_DATA..=SERIAL_CONTROL).contains(&addr) {
+            self.serial.write_forced(addr, value);
+        } else if self.cgb_mode && is_cgb_register(addr) {
+            match addr {
+                BCPD => self.bg_palettes[(self.mem[BCPS as usize] & 0x3F) as usize] = value,
+                OCPD => self.obj_palettes[(self.mem[OCPS as usize] & 0x3F) as usize] = value,
+                HDMA5 => {}
+                _ => self.write_cgb_register(addr, value),
+            }
+        } else {
+            self.mem[addr as usize] = value;
+        }
+    }
+
+    /// Number of banks in a region
+    pub fn bank_count(&self, region: MemoryRegion) -> usize {
+        match region {
+            MemoryRegion::Rom => self.rom_len.div_ceil(0x4000),
+            MemoryRegion::CartRam => self.external_ram.len(),
+            MemoryRegion::Vram if self.model.is_cgb() => 2,
+            MemoryRegion::Wram if self.model.is_cgb() => 8,
+            MemoryRegion::Wram => 2,
+            _ => 1,
+        }
+    }
+
+    /// Reads a byte from a bank whether or not it is mapped
+    pub fn peek_bank(
+        &self,
+        region: MemoryRegion,
+        bank: usize,
+        offset: usize,
+    ) -> Result<Byte, &'static str> {
+        if offset >= region.bank_size() {
+            return Err("Offset is past the end of the bank");
+        }
+        if region == MemoryRegion::Io {
+            return Ok(self.read_byte_internal(0xFF00 + offset as Word));
+        }
+        self.bank(region, bank)
+            .map(|data| data[offset])
+            .ok_or("No such bank")
+    }
+
+    /// Writes a byte to a bank whether or not it is mapped, see `poke`
+    pub fn poke_bank(
+        &mut self,
+        region: MemoryRegion,
+        bank: usize,
+        offset: usize,
+        value: Byte,
+    ) -> Result<(), &'static str> {
+        if offset >= region.bank_size() {
+            return Err("Offset is past the end of the bank");
+        }
+        if region == MemoryRegion::Io {
+            self.poke(0xFF00 + offset as Word, value);
+            return Ok(());
+        }
+        let data = self.bank_mut(region, bank).ok_or("No such bank")?;
+        data[offset] = value;
+        Ok(())
+    }
+
+    /// Region, bank and offset currently mapped at an address, for the
+    /// regions that are plain storage
+    fn locate(&self, addr: Word) -> Option<(MemoryRegion, usize, usize)> {
+        let addr = addr as usize;
+        match addr {
+            0x0000..0x4000 => Some((MemoryRegion::Rom, 0, addr)),
+            // Banks past the end of the ROM wrap around, as they do for reads
+            0x4000..0x8000 => Some((
+                MemoryRegion::Rom,
+                self.rom_banks.value() as usize % self.bank_count(MemoryRegion::Rom).max(1),
+                addr - 0x4000,
+            )),
+            0x8000..0xA000 => Some((MemoryRegion::Vram, self.vram_bank, addr - 0x8000)),
+            0xA000..0xC000 => Some((
+                MemoryRegion::CartRam,
+                self.ram_banks as usize,
+                addr - 0xA000,
+            )),
+            0xC000..0xD000 => Some((MemoryRegion::Wram, 0, addr - 0xC000)),
+            0xD000..0xE000 => Some((MemoryRegion::Wram, self.wram_bank, addr - 0xD000)),
+            0xFE00..0xFEA0 => Some((MemoryRegion::Oam, 0, addr - 0xFE00)),
+            0xFF80..0xFFFF => Some((MemoryRegion::Hram, 0, addr - 0xFF80)),
+            _ => None,
+        }
+    }
+
+    fn bank(&self, region: MemoryRegion, bank: usize) -> Option<&[Byte]> {
+        if bank >= self.bank_count(region) {
+            return None;
+        }
+        let size = region.bank_size();
+        Some(match region {
+            MemoryRegion::Rom => &self.rom[bank * size..(bank + 1) * size],
+            MemoryRegion::CartRam => &self.external_ram[bank],
+            MemoryRegion::Vram if bank == 1 => &self.vram_bank1,
+            MemoryRegion::Vram => &self.mem[0x8000..0xA000],
+            MemoryRegion::Wram if bank > 1 => &self.wram_banks[bank - 2],
+            MemoryRegion::Wram => &self.mem[0xC000 + bank * size..0xD000 + bank * size],
+            MemoryRegion::Oam => &self.mem[0xFE00..0xFEA0],
+            MemoryRegion::Hram => &self.mem[0xFF80..0xFFFF],
+            MemoryRegion::Io => return None,
+        })
+    }
+
+    fn bank_mut(&mut self, region: MemoryRegion, bank: usize) -> Option<&mut [Byte]> {
+        if bank >= self.bank_count(region) {
+            return None;
+        }
+        let size = region.bank_size();
+        Some(match region {
+            MemoryRegion::Rom => &mut self.rom[bank * size..(bank + 1) * size],
+            MemoryRegion::CartRam => &mut self.external_ram[bank],
+            MemoryRegion::Vram if bank == 1 => &mut self.vram_bank1,
+            MemoryRegion::Vram => &mut self.mem[0x8000..0xA000],
+            MemoryRegion::Wram if bank > 1 => &mut self.wram_banks[bank - 2],
+            MemoryRegion::Wram => &mut self.mem[0xC000 + bank * size..0xD000 + bank * size],
+            MemoryRegion::Oam => &mut self.mem[0xFE00..0xFEA0],
+            MemoryRegion::Hram => &mut self.mem[0xFF80..0xFFFF],
+            MemoryRegion::Io => return None,
+        })
+    }
+
+    #[allow(dead_code)]
     /// Reads TMC memory location to get current clock frequency
     ///
     /// Selects bits 0 and 1 to map to a frequency value
@@ -149,20 +490,192 @@ impl Memory {
         self.read_byte(TMC) & 0x3
     }
 
-    /// Sets the timer_counter to the current clock frequency
-    pub fn set_clock_frequency(&mut self) {
-        let frequency = self.get_clock_freq();
+    /// Advances DIV and TIMA by the given number of cycles
+    ///
+    /// Requests the timer interrupt when TIMA is reloaded after an overflow
+    pub fn update_timers(&mut self, cycles: i32) {
+        if self.timer.tick(cycles) {
+            self.request_interrupt(2);
+        }
+    }
+
+    /// Advances an active serial transfer by the given number of cycles
+    pub fn update_serial(&mut self, cycles: i32) {
+        if self.serial.tick(cycles) {
+            self.request_interrupt(3);
+        }
+    }
+
+    /// Copies the next byte of a running OAM DMA
+    pub fn update_dma(&mut self) {
+        let Some(mut dma) = self.dma else {
+            return;
+        };
+
+        if dma.delay > 0 {
+            dma.delay -= 1;
+        } else {
+            let memory = self.read_byte(dma.source + dma.index);
+            self.mem[(SPRITE_RAM + dma.index) as usize] = memory;
+            dma.index += 1;
+        }
+
+        self.dma = if dma.index < 0xA0 { Some(dma) } else { None };
+    }
+
+    /// Whether an OAM DMA currently owns the sprite RAM
+    pub fn dma_active(&self) -> bool {
+        matches!(self.dma, Some(dma) if dma.index > 0)
+    }
+
+    /// Hands the start of the address space back to the cartridge
+    ///
+    /// The CGB boot ROM picks the DMG compatibility mode by writing 0x04 to
+    /// KEY0 before it finishes, which can only be done while it is mapped.
+    fn unmap_boot_rom(&mut self) {
+        self.boot_rom_mapped = false;
+        if self.model.is_cgb() && self.cgb_boot_rom() {
+            self.cgb_mode = self.mem[KEY0 as usize] & 0x04 == 0;
+        }
+    }
+
+    /// Only the larger CGB boot ROM knows about KEY0 and CGB mode
+    fn cgb_boot_rom(&self) -> bool {
+        self.boot_rom_len == BOOT_ROM_SIZE_CGB
+    }
+
+    /// Called by the PPU when it enters HBlank, copies the next HDMA block
+    pub fn hblank(&mut self) {
+        if self.hdma.active {
+            self.hdma_copy_block();
+            if self.hdma.remaining == 0x7F {
+                self.hdma.active = false;
+            }
+        }
+    }
+
+    /// Takes the M-cycles the CPU must be held for VRAM DMA copies
+    pub fn take_dma_stall(&mut self) -> u32 {
+        core::mem::take(&mut self.dma_stall)
+    }
+
+    /// Performs a speed switch if one was requested through KEY1
+    ///
+    /// Returns true if the speed changed
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed {
+            return false;
+        }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        true
+    }
 
-        // Magic numbers again frome from val of CLOCKSPEED / frequency
-        match frequency {
-            0 => self.timer_counter = 1024, // freq = 4096
-            1 => self.timer_counter = 16,   // freq = 262144
-            2 => self.timer_counter = 64,   // freq = 65536
-            3 => self.timer_counter = 256,  // freq = 16382
-            _ => self.timer_counter = 1024, // default
+    /// Reads VRAM from a specific bank, ignoring VBK
+    pub fn read_vram(&self, bank: usize, addr: Word) -> Byte {
+        if bank == 1 {
+            self.vram_bank1[(addr - 0x8000) as usize]
+        } else {
+            self.mem[addr as usize]
+        }
+    }
+
+    /// 15-bit color from a CGB background palette
+    pub fn bg_palette_color(&self, palette: Byte, color_num: Byte) -> Word {
+        palette_color(&self.bg_palettes, palette, color_num)
+    }
+
+    /// 15-bit color from a CGB sprite palette
+    pub fn obj_palette_color(&self, palette: Byte, color_num: Byte) -> Word {
+        palette_color(&self.obj_palettes, palette, color_num)
+    }
+
+    fn write_cgb_register(&mut self, addr: Word, value: Byte) {
+        match addr {
+            KEY1 => self.speed_switch_armed = value & 0x1 != 0,
+            VBK => self.vram_bank = (value & 0x1) as usize,
+            HDMA1 => self.hdma.source = (self.hdma.source & 0x00F0) | ((value as Word) << 8),
+            HDMA2 => self.hdma.source = (self.hdma.source & 0xFF00) | (value & 0xF0) as Word,
+            HDMA3 => self.hdma.dest = (self.hdma.dest & 0x00F0) | (((value & 0x1F) as Word) << 8),
+            HDMA4 => self.hdma.dest = (self.hdma.dest & 0x1F00) | (value & 0xF0) as Word,
+            HDMA5 => self.start_hdma(value),
+            BCPS | OCPS => self.mem[addr as usize] = value & 0xBF,
+            BCPD => {
+                let index = self.palette_index(BCPS);
+                self.bg_palettes[index] = value;
+            }
+            OCPD => {
+                let index = self.palette_index(OCPS);
+                self.obj_palettes[index] = value;
+            }
+            SVBK => self.wram_bank = ((value & 0x7) as usize).max(1),
+            _ => {}
         }
     }
 
+    fn read_cgb_register(&self, addr: Word) -> Byte {
+        match addr {
+            KEY1 => 0x7E | ((self.double_speed as Byte) << 7) | self.speed_switch_armed as Byte,
+            VBK => 0xFE | self.vram_bank as Byte,
+            HDMA5 => ((!self.hdma.active as Byte) << 7) | self.hdma.remaining,
+            BCPS | OCPS => self.mem[addr as usize] | 0x40,
+            BCPD => self.bg_palettes[(self.mem[BCPS as usize] & 0x3F) as usize],
+            OCPD => self.obj_palettes[(self.mem[OCPS as usize] & 0x3F) as usize],
+            SVBK => 0xF8 | self.wram_bank as Byte,
+            // The rest of the HDMA registers are write only
+            _ => 0xFF,
+        }
+    }
+
+    /// Index selected by BCPS/OCPS, advancing it if auto increment is set
+    fn palette_index(&mut self, spec: Word) -> usize {
+        let value = self.mem[spec as usize];
+        if value & 0x80 != 0 {
+            self.mem[spec as usize] = 0x80 | (value.wrapping_add(1) & 0x3F);
+        }
+        (value & 0x3F) as usize
+    }
+
+    /// Handles a write to HDMA5
+    ///
+    /// Bit 7 picks between copying everything now (GDMA) or one block per
+    /// HBlank (HDMA). Writing bit 7 clear while an HDMA runs stops it instead.
+    fn start_hdma(&mut self, value: Byte) {
+        if self.hdma.active && value & 0x80 == 0 {
+            self.hdma.active = false;
+            return;
+        }
+
+        self.hdma.remaining = value & 0x7F;
+        if value & 0x80 != 0 {
+            self.hdma.active = true;
+            // With the LCD off there is no HBlank so the first block goes straight away
+            if self.mem[LCD_CONTROL as usize] & 0x80 == 0 {
+                self.hblank();
+            }
+        } else {
+            loop {
+                self.hdma_copy_block();
+                if self.hdma.remaining == 0x7F {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn hdma_copy_block(&mut self) {
+        for i in 0..0x10 {
+            let value = self.read_byte(self.hdma.source.wrapping_add(i));
+            let dest = 0x8000 | ((self.hdma.dest + i) & 0x1FFF);
+            self.write_byte(dest, value);
+        }
+        self.hdma.source = self.hdma.source.wrapping_add(0x10);
+        self.hdma.dest = (self.hdma.dest + 0x10) & 0x1FF0;
+        self.hdma.remaining = self.hdma.remaining.wrapping_sub(1) & 0x7F;
+        // Each block holds the CPU for 8 M-cycles at normal speed
+        self.dma_stall += if self.double_speed { 16 } else { 8 };
+    }
+
     fn handle_banking(&mut self, addr: Word, value: Byte) {
         // Performs a ram bank change
         if addr < 0x2000 {
@@ -258,11 +771,12 @@ impl Memory {
 
     /// Function for setting ram to requred startup values
     ///
-    /// Its pretty messy but ripped straight from most gameboy dev docs
+    /// Its pretty messy but ripped straight from most gameboy dev docs, with
+    /// the few registers that differ between models set at the end
     pub fn ram_startup(&mut self) {
-        self.mem[0xFF05] = 0x00;
-        self.mem[0xFF06] = 0x00;
-        self.mem[0xFF07] = 0x00;
+        self.write_byte_forced(0xFF05, 0x00);
+        self.write_byte_forced(0xFF06, 0x00);
+        self.write_byte_forced(0xFF07, 0x00);
         self.mem[0xFF10] = 0x80;
         self.mem[0xFF11] = 0xBF;
         self.mem[0xFF12] = 0xF3;
@@ -291,7 +805,104 @@ impl Memory {
         self.mem[0xFF4A] = 0x00;
         self.mem[0xFF4B] = 0x00;
         self.mem[0xFFFF] = 0x00;
+        self.mem[IF as usize] = 0xE1;
+        self.mem[INPUT_REGISTER as usize] = 0xCF;
+        self.recompute_joypad();
         self.refresh_rom_banking_type();
+
+        // Outside of CGB mode the CGB registers aren't there and read as 0xFF
+        for addr in [
+            KEY0, KEY1, VBK, HDMA1, HDMA2, HDMA3, HDMA4, HDMA5, BCPS, BCPD, OCPS, OCPD, SVBK,
+        ] {
+            self.mem[addr as usize] = 0xFF;
+        }
+        if self.model.is_cgb() {
+            // What the boot ROM wrote to KEY0 to pick the mode
+            self.mem[KEY0 as usize] = if self.cgb_mode { self.rom[0x143] } else { 0x04 };
+        }
+        if self.cgb_mode {
+            // The boot ROM sets every background color through BCPS with auto
+            // increment, which wraps it back around to index 0
+            self.mem[BCPS as usize] = 0x80;
+            self.mem[OCPS as usize] = 0x00;
+        }
+
+        self.timer.set_counter(match self.model {
+            // Only the upper byte is known for the DMG0
+            Model::Dmg0 => 0x1800,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            // How long these boot ROMs run depends on the cartridge, only a
+            // real boot ROM gets DIV exactly right
+            Model::Sgb | Model::Sgb2 | Model::Cgb => 0x0000,
+        });
+        // The DMG0 boot ROM hands over in the middle of VBlank, the others
+        // right as the LCD starts again
+        let (stat, ly) = match self.model {
+            Model::Dmg0 => (0x81, 0x91),
+            _ => (0x85, 0x00),
+        };
+        self.mem[LCD_STATUS as usize] = stat;
+        self.mem[CURRENT_SCANLINE as usize] = ly;
+        self.mem[DMA_REG as usize] = if self.model.is_cgb() { 0x00 } else { 0xFF };
+        if self.model.is_sgb() {
+            self.mem[0xFF26] = 0xF0;
+        }
+        if self.model.is_cgb() {
+            self.serial.write(SERIAL_CONTROL, 0x01);
+        }
+        if matches!(self.model, Model::Dmg0 | Model::Dmg | Model::Mgb) {
+            self.load_boot_logo();
+        }
+    }
+
+    /// Leaves the Nintendo logo from the cartridge header in VRAM
+    ///
+    /// The DMG boot ROMs stretch every bit of the 48 byte logo to 2x2 pixels,
+    /// filling tiles 1-24, add the (R) as tile 25 and lay them out in the
+    /// background map where the logo stopped scrolling.
+    fn load_boot_logo(&mut self) {
+        const REGISTERED: [Byte; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+        let mut addr = 0x8010;
+        for i in 0..0x30 {
+            let logo = self.read_rom_byte(0x104 + i);
+            for nibble in [logo >> 4, logo & 0xF] {
+                let mut doubled = 0;
+                for bit in 0..4 {
+                    if nibble & (1 << bit) != 0 {
+                        doubled |= 0b11 << (bit * 2);
+                    }
+                }
+                self.mem[addr] = doubled;
+                self.mem[addr + 2] = doubled;
+                addr += 4;
+            }
+        }
+
+        for (i, row) in REGISTERED.iter().enumerate() {
+            self.mem[0x8190 + i * 2] = *row;
+        }
+
+        for tile in 1..=12 {
+            self.mem[0x9903 + tile] = tile as Byte;
+            self.mem[0x9923 + tile] = tile as Byte + 12;
+        }
+        self.mem[0x9910] = 0x19;
+    }
+
+    /// Sum of the title bytes for cartridges published by Nintendo
+    ///
+    /// The CGB boot ROM only looks up Nintendo games when picking registers
+    /// and colors for DMG compatibility mode
+    pub fn title_checksum(&self) -> Option<Byte> {
+        let old_licensee = self.read_rom_byte(0x14B);
+        let new_licensee = [self.read_rom_byte(0x144), self.read_rom_byte(0x145)];
+        let nintendo = old_licensee == 0x01 || (old_licensee == 0x33 && new_licensee == *b"01");
+        nintendo.then(|| {
+            (0x134..0x144).fold(0, |sum: Byte, addr| {
+                sum.wrapping_add(self.read_rom_byte(addr))
+            })
+        })
     }
 
     /// Checks to get the current rom banking type
@@ -316,14 +927,43 @@ impl Memory {
         self.recompute_joypad();
     }
 
+    /// Whether any of the joypad lines selected in P1 are being held low
+    pub fn joypad_line_low(&self) -> bool {
+        self.mem[INPUT_REGISTER as usize] & 0x0F != 0x0F
+    }
+
     /// Loads the given ROM bytes into memory
     pub fn load_rom_data(&mut self, data: &[u8]) {
-        self.mem.fill(0); // clear VRAM, WRAM, OAM, I/O mirrors
         self.rom.fill(0);
         let copy_len = core::cmp::min(data.len(), MAX_ROM_SIZE);
         self.rom[..copy_len].copy_from_slice(&data[..copy_len]);
         self.rom_len = copy_len;
+        self.restart();
+    }
+
+    /// Clears everything but the ROM, boot ROM and cheats, like turning the
+    /// system off and on again
+    pub fn restart(&mut self) {
+        self.mem.fill(0); // clear VRAM, WRAM, OAM, I/O mirrors
+        self.timer = Timer::new();
+        self.serial.reset();
+        self.dma = None;
         self.external_ram = [[0; 0x2000]; 4];
+        self.vram_bank1 = [0; 0x2000];
+        self.wram_banks = [[0; 0x1000]; 6];
+        // The CGB boot ROM leaves every background color white
+        self.bg_palettes = [0xFF; 64];
+        self.obj_palettes = [0; 64];
+        // A CGB boot ROM runs in CGB mode and decides on compatibility mode
+        // itself, a DMG one never touches KEY0 so the header decides
+        self.boot_rom_mapped = self.boot_rom_len > 0;
+        self.cgb_mode = self.model.is_cgb() && (self.cgb_boot_rom() || self.rom[0x143] & 0x80 != 0);
+        self.vram_bank = 0;
+        self.wram_bank = 1;
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.hdma = Hdma::new();
+        self.dma_stall = 0;
 
         self.rom_banks = CurrentRomBank::Bank(1);
         self.ram_banks = CurrentRamBank::Bank0;
@@ -333,16 +973,49 @@ impl Memory {
     }
 
     fn read_byte_internal(&self, addr: Word) -> Byte {
+        // timer registers live outside of the flat memory
+        if (DIVIDER_REGISTER..=TMC).contains(&addr) {
+            return self.timer.read(addr);
+        }
+
+        if (SERIAL_DATA..=SERIAL_CONTROL).contains(&addr) {
+            return self.serial.read(addr);
+        }
+
+        if (0x8000..0xA000).contains(&addr) && self.vram_bank == 1 {
+            return self.vram_bank1[(addr - 0x8000) as usize];
+        }
+
+        // Echo RAM mirrors WRAM, including whichever bank SVBK maps at 0xD000
+        let addr = if (0xE000..0xFE00).contains(&addr) {
+            addr - 0x2000
+        } else {
+            addr
+        };
+        if (0xD000..0xE000).contains(&addr) && self.wram_bank > 1 {
+            return self.wram_banks[self.wram_bank - 2][(addr - 0xD000) as usize];
+        }
+
+        if self.cgb_mode && is_cgb_register(addr) {
+            return self.read_cgb_register(addr);
+        }
+
+        if self.boot_rom_mapped && self.in_boot_rom(addr) {
+            return self.boot_rom[addr as usize];
+        }
+
         // read from the always consistant rom bank
         if addr < 0x4000 {
-            return self.read_rom_byte(addr as usize);
+            let value = self.read_rom_byte(addr as usize);
+            return self.cheats.patch_rom(addr, value);
         }
 
         // map to rom banking
         if (0x4000..=0x7FFF).contains(&addr) {
             let relative = (addr - 0x4000) as usize;
             let offset = (self.rom_banks.value() as usize) * 0x4000;
-            return self.read_rom_byte(offset + relative);
+            let value = self.read_rom_byte(offset + relative);
+            return self.cheats.patch_rom(addr, value);
         }
 
         // map to ram banking
@@ -355,6 +1028,11 @@ impl Memory {
         self.mem[addr as usize]
     }
 
+    /// The CGB boot ROM leaves a gap at 0x100-0x1FF for the cartridge header
+    fn in_boot_rom(&self, addr: Word) -> bool {
+        addr < 0x100 || (self.boot_rom_len == BOOT_ROM_SIZE_CGB && (0x200..0x900).contains(&addr))
+    }
+
     fn recompute_joypad(&mut self) {
         let prev = self.mem[INPUT_REGISTER as usize];
 
@@ -403,13 +1081,16 @@ impl Memory {
         self.write_byte(IE, enabled);
     }
 
-    /// Sets sprite into the sprite ram
+    /// Starts copying sprites into the sprite ram
+    ///
+    /// The copy runs in the background over the next 161 M-cycles
     fn dma_transfer(&mut self, value: Byte) {
-        let address: Word = (value as Word) << 8; // source address is data * 100
-        for i in 0..0xA0 {
-            let memory = self.read_byte(address + i);
-            self.write_byte(SPRITE_RAM + i, memory);
-        }
+        self.mem[DMA_REG as usize] = value;
+        self.dma = Some(Dma {
+            source: (value as Word) << 8, // source address is data * 100
+            index: 0,
+            delay: 1,
+        });
     }
 
     pub fn get_color(&self, color_num: Byte, addr: Word) -> Result<Color, Error> {
@@ -435,6 +1116,20 @@ impl Memory {
     }
 }
 
+/// Registers that only exist on the CGB
+fn is_cgb_register(addr: Word) -> bool {
+    matches!(
+        addr,
+        KEY1 | VBK | HDMA1..=HDMA5 | BCPS..=OCPD | SVBK
+    )
+}
+
+/// Looks up a little endian 15-bit color in palette RAM
+fn palette_color(ram: &[Byte; 64], palette: Byte, color_num: Byte) -> Word {
+    let index = (palette as usize & 0x7) * 8 + (color_num as usize & 0x3) * 2;
+    (ram[index] as Word | (ram[index + 1] as Word) << 8) & 0x7FFF
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -644,20 +1339,16 @@ mod test {
         let mut mem = Memory::new();
 
         mem.write_byte_forced(TMC, 0);
-        mem.set_clock_frequency();
-        assert_eq!(mem.timer_counter, 1024);
+        assert_eq!(mem.read_byte(TMC) & 0x3, 0);
 
         mem.write_byte_forced(TMC, 1);
-        mem.set_clock_frequency();
-        assert_eq!(mem.timer_counter, 16);
+        assert_eq!(mem.read_byte(TMC) & 0x3, 1);
 
         mem.write_byte_forced(TMC, 2);
-        mem.set_clock_frequency();
-        assert_eq!(mem.timer_counter, 64);
+        assert_eq!(mem.read_byte(TMC) & 0x3, 2);
 
         mem.write_byte_forced(TMC, 3);
-        mem.set_clock_frequency();
-        assert_eq!(mem.timer_counter, 256);
+        assert_eq!(mem.read_byte(TMC) & 0x3, 3);
     }
 
     #[test]
@@ -672,6 +1363,17 @@ mod test {
 
         // Trigger DMA transfer from 0xC000 to sprite RAM
         mem.write_byte(DMA_REG, 0xC0);
+        assert!(!mem.dma_active());
+
+        // One cycle of setup then a byte per cycle
+        mem.update_dma();
+        mem.update_dma();
+        assert!(mem.dma_active());
+        assert_eq!(mem.read_byte(SPRITE_RAM), 0);
+        for _ in 0..0x9F {
+            mem.update_dma();
+        }
+        assert!(!mem.dma_active());
 
         for i in 0..0xA0 {
             assert_eq!(mem.read_byte(SPRITE_RAM + i), i as Byte);
@@ -681,17 +1383,44 @@ mod test {
     #[test]
     #[timeout(100)]
     fn test_set_clock_frequency() {
-        let mut mem = Memory::new();
-
         let tests = [(0x0, 1024), (0x1, 16), (0x2, 64), (0x3, 256)];
 
         for (val, expected) in tests {
-            mem.write_byte_forced(TMC, val);
-            mem.set_clock_frequency();
-            assert_eq!(mem.timer_counter, expected);
+            let mut mem = Memory::new();
+            mem.write_byte(TMC, 0x4 | val);
+            mem.update_timers(expected - 4);
+            assert_eq!(mem.read_byte(TIMA), 0);
+            mem.update_timers(4);
+            assert_eq!(mem.read_byte(TIMA), 1);
         }
     }
 
+    #[test]
+    #[timeout(100)]
+    fn test_timer_overflow_requests_interrupt() {
+        let mut mem = Memory::new();
+        mem.write_byte(TMA, 0x80);
+        mem.write_byte(TIMA, 0xFF);
+        mem.write_byte(TMC, 0x5);
+
+        mem.update_timers(16);
+        assert_eq!(mem.read_byte(IF) & (1 << 2), 0);
+        mem.update_timers(4);
+        assert_eq!(mem.read_byte(IF) & (1 << 2), 1 << 2);
+        assert_eq!(mem.read_byte(TIMA), 0x80);
+    }
+
+    #[test]
+    #[timeout(100)]
+    fn test_div_write_resets() {
+        let mut mem = Memory::new();
+        mem.update_timers(0x300);
+        assert_eq!(mem.read_byte(DIVIDER_REGISTER), 3);
+
+        mem.write_byte(DIVIDER_REGISTER, 0xAB);
+        assert_eq!(mem.read_byte(DIVIDER_REGISTER), 0);
+    }
+
     #[test]
     #[timeout(100)]
     fn test_request_enable_interrupt() {
@@ -754,6 +1483,7 @@ mod test {
 
     #[test]
     #[timeout(10)]
+    #[allow(clippy::needless_range_loop)]
     fn test_load_rom_data_truncate() {
         let mut mem = Memory::new();
         let mut data = vec![0u8; 0x9000];
@@ -807,4 +1537,463 @@ mod test {
         assert!(mem.rom_bank_enable);
         assert!(!mem.ram_write_enable);
     }
+
+    #[test]
+    #[timeout(10)]
+    fn test_poke_has_no_side_effects() {
+        let mut mem = Memory::new();
+        mem.ram_startup();
+
+        mem.poke(DMA_REG, 0xC0);
+        assert!(!mem.dma_active());
+        assert_eq!(mem.read_byte(DMA_REG), 0xC0);
+
+        mem.poke(DIVIDER_REGISTER, 0x12);
+        assert_eq!(mem.read_byte(DIVIDER_REGISTER), 0x12);
+
+        mem.poke(INPUT_REGISTER, 0xC0);
+        assert_eq!(mem.read_byte(IF) & 0x10, 0);
+
+        // ROM is patched rather than switching banks
+        mem.load_rom_data(&[0; 0x8000]);
+        mem.poke(0x2000, 0x05);
+        assert_eq!(mem.rom_banks, CurrentRomBank::Bank(1));
+        assert_eq!(mem.read_byte(0x2000), 0x05);
+        mem.poke(0x4000, 0x06);
+        assert_eq!(mem.peek_bank(MemoryRegion::Rom, 1, 0), Ok(0x06));
+
+        // A bank number past the end of the ROM pokes the bank that is read
+        mem.rom_banks = CurrentRomBank::Bank(3);
+        mem.poke(0x4001, 0x07);
+        assert_eq!(mem.read_byte(0x4001), 0x07);
+        assert_eq!(mem.peek_bank(MemoryRegion::Rom, 1, 1), Ok(0x07));
+
+        // Echo RAM pokes land in both copies
+        mem.poke(0xE010, 0x33);
+        assert_eq!(mem.read_byte(0xC010), 0x33);
+        assert_eq!(mem.mem[0xE010], 0x33);
+
+        // Disabled cartridge RAM is written anyway
+        mem.poke(0xA000, 0x42);
+        assert_eq!(mem.read_byte(0xA000), 0x42);
+        assert!(!mem.ram_write_enable);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_bank_access() {
+        let mut mem = cgb_memory();
+        assert_eq!(mem.bank_count(MemoryRegion::Rom), 2);
+        assert_eq!(mem.bank_count(MemoryRegion::Vram), 2);
+        assert_eq!(mem.bank_count(MemoryRegion::Wram), 8);
+
+        // Unmapped banks can be reached without switching
+        mem.write_byte(SVBK, 5);
+        mem.write_byte(0xD010, 0x55);
+        mem.write_byte(SVBK, 1);
+        assert_eq!(mem.peek_bank(MemoryRegion::Wram, 5, 0x10), Ok(0x55));
+        mem.poke_bank(MemoryRegion::Vram, 1, 0x20, 0x66).unwrap();
+        assert_eq!(mem.read_byte(0x8020), 0);
+        assert_eq!(mem.read_vram(1, 0x8020), 0x66);
+        mem.poke_bank(MemoryRegion::CartRam, 3, 0, 0x77).unwrap();
+        assert_eq!(mem.external_ram[3][0], 0x77);
+
+        mem.poke_bank(MemoryRegion::Hram, 0, 0x7E, 0x88).unwrap();
+        assert_eq!(mem.read_byte(0xFFFE), 0x88);
+        mem.poke_bank(MemoryRegion::Oam, 0, 0x9F, 0x99).unwrap();
+        assert_eq!(mem.read_byte(0xFE9F), 0x99);
+        assert_eq!(mem.peek_bank(MemoryRegion::Io, 0, 0x70), Ok(0xF9));
+
+        assert!(mem.peek_bank(MemoryRegion::Rom, 2, 0).is_err());
+        assert!(mem.peek_bank(MemoryRegion::Oam, 0, 0xA0).is_err());
+        assert!(mem.poke_bank(MemoryRegion::Wram, 8, 0, 0).is_err());
+    }
+
+    fn cgb_memory() -> Memory {
+        let mut mem = Memory::new();
+        mem.set_model(Model::Cgb);
+        let mut rom = [0; 0x8000];
+        rom[0x143] = 0x80;
+        mem.load_rom_data(&rom);
+        mem.ram_startup();
+        mem
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_cgb_mode_needs_cgb_cartridge() {
+        let mut mem = Memory::new();
+        mem.set_model(Model::Cgb);
+        mem.load_rom_data(&[0; 0x8000]);
+        mem.ram_startup();
+        assert!(!mem.cgb_mode());
+
+        // Without CGB mode VBK is just plain memory
+        mem.write_byte(0x8000, 0x11);
+        mem.write_byte(VBK, 0x1);
+        assert_eq!(mem.read_byte(0x8000), 0x11);
+
+        let mem = cgb_memory();
+        assert!(mem.cgb_mode());
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_cgb_registers_after_boot() {
+        let mut mem = cgb_memory();
+        assert_eq!(mem.read_byte(KEY1), 0x7E);
+        assert_eq!(mem.read_byte(VBK), 0xFE);
+        assert_eq!(mem.read_byte(SVBK), 0xF9);
+        for addr in HDMA1..=HDMA5 {
+            assert_eq!(mem.read_byte(addr), 0xFF);
+        }
+        assert_eq!(mem.read_byte(BCPS), 0xC0);
+        assert_eq!(mem.read_byte(OCPS), 0x40);
+
+        // A DMG, or a CGB running an older game, doesn't have them
+        for model in [Model::Dmg, Model::Cgb] {
+            mem.set_model(model);
+            mem.load_rom_data(&[0; 0x8000]);
+            mem.ram_startup();
+            for addr in [KEY1, VBK, HDMA1, HDMA5, BCPS, BCPD, OCPS, OCPD, SVBK] {
+                assert_eq!(mem.read_byte(addr), 0xFF);
+            }
+        }
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_vram_banks() {
+        let mut mem = cgb_memory();
+        mem.write_byte(0x8000, 0x11);
+        mem.write_byte(VBK, 0x1);
+        assert_eq!(mem.read_byte(VBK), 0xFF);
+        mem.write_byte(0x8000, 0x22);
+        assert_eq!(mem.read_byte(0x8000), 0x22);
+
+        mem.write_byte(VBK, 0x0);
+        assert_eq!(mem.read_byte(VBK), 0xFE);
+        assert_eq!(mem.read_byte(0x8000), 0x11);
+
+        // The PPU can read either bank regardless of VBK
+        assert_eq!(mem.read_vram(1, 0x8000), 0x22);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_wram_banks() {
+        let mut mem = cgb_memory();
+        assert_eq!(mem.read_byte(SVBK), 0xF9);
+
+        for bank in 1..8 {
+            mem.write_byte(SVBK, bank);
+            mem.write_byte(0xD000, bank * 0x10);
+        }
+        for bank in 1..8 {
+            mem.write_byte(SVBK, bank);
+            assert_eq!(mem.read_byte(0xD000), bank * 0x10);
+        }
+
+        // Bank 0 selects bank 1 and the fixed bank is never switched
+        mem.write_byte(0xC000, 0x99);
+        mem.write_byte(SVBK, 0);
+        assert_eq!(mem.read_byte(SVBK), 0xF9);
+        assert_eq!(mem.read_byte(0xD000), 0x10);
+        assert_eq!(mem.read_byte(0xC000), 0x99);
+
+        // Echo RAM follows the switched bank too
+        mem.write_byte(SVBK, 5);
+        assert_eq!(mem.read_byte(0xF000), 0x50);
+        mem.write_byte(0xF001, 0x55);
+        assert_eq!(mem.read_byte(0xD001), 0x55);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_palette_auto_increment() {
+        let mut mem = cgb_memory();
+        assert_eq!(mem.bg_palette_color(0, 0), 0x7FFF);
+
+        // Palette 1 color 2 is at index 0x0C
+        mem.write_byte(BCPS, 0x8C);
+        mem.write_byte(BCPD, 0x1F);
+        mem.write_byte(BCPD, 0x7C);
+        assert_eq!(mem.read_byte(BCPS), 0xCE);
+        assert_eq!(mem.bg_palette_color(1, 2), 0x7C1F);
+
+        // Without auto increment the index stays put
+        mem.write_byte(OCPS, 0x3F);
+        mem.write_byte(OCPD, 0x12);
+        mem.write_byte(OCPD, 0x34);
+        assert_eq!(mem.read_byte(OCPS), 0x7F);
+        assert_eq!(mem.read_byte(OCPD), 0x34);
+        assert_eq!(mem.obj_palette_color(7, 3) >> 8, 0x34);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_general_purpose_dma() {
+        let mut mem = cgb_memory();
+        for i in 0..0x20 {
+            mem.write_byte(0xC000 + i, i as Byte);
+        }
+
+        mem.write_byte(HDMA1, 0xC0);
+        mem.write_byte(HDMA2, 0x00);
+        mem.write_byte(HDMA3, 0x01);
+        mem.write_byte(HDMA4, 0x00);
+        mem.write_byte(HDMA5, 0x01);
+
+        for i in 0..0x20 {
+            assert_eq!(mem.read_byte(0x8100 + i), i as Byte);
+        }
+        assert_eq!(mem.read_byte(HDMA5), 0xFF);
+        assert_eq!(mem.take_dma_stall(), 16);
+        assert_eq!(mem.take_dma_stall(), 0);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_hblank_dma() {
+        let mut mem = cgb_memory();
+        mem.write_byte(LCD_CONTROL, 0x91);
+        for i in 0..0x30 {
+            mem.write_byte(0xC000 + i, 0xA0 | i as Byte);
+        }
+
+        mem.write_byte(HDMA1, 0xC0);
+        mem.write_byte(HDMA2, 0x00);
+        mem.write_byte(HDMA3, 0x00);
+        mem.write_byte(HDMA4, 0x00);
+        mem.write_byte(HDMA5, 0x82);
+        assert_eq!(mem.read_byte(HDMA5), 0x02);
+        assert_eq!(mem.read_byte(0x8000), 0);
+
+        mem.hblank();
+        assert_eq!(mem.read_byte(0x800F), 0xAF);
+        assert_eq!(mem.read_byte(0x8010), 0);
+        assert_eq!(mem.read_byte(HDMA5), 0x01);
+
+        // Stopping the transfer leaves bit 7 set with the blocks remaining
+        mem.write_byte(HDMA5, 0x00);
+        assert_eq!(mem.read_byte(HDMA5), 0x81);
+        mem.hblank();
+        assert_eq!(mem.read_byte(0x8010), 0);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_speed_switch() {
+        let mut mem = cgb_memory();
+        assert_eq!(mem.read_byte(KEY1), 0x7E);
+        assert!(!mem.switch_speed());
+
+        mem.write_byte(KEY1, 0x1);
+        assert_eq!(mem.read_byte(KEY1), 0x7F);
+        assert!(mem.switch_speed());
+        assert!(mem.double_speed());
+        assert_eq!(mem.read_byte(KEY1), 0xFE);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_boot_rom_mapped_until_ff50() {
+        let mut mem = Memory::new();
+        assert!(mem.set_boot_rom(&[0x42; 0x20]).is_err());
+        mem.set_boot_rom(&[0x42; BOOT_ROM_SIZE]).unwrap();
+
+        let mut rom = [0x11; 0x8000];
+        rom[0x143] = 0x80;
+        mem.load_rom_data(&rom);
+        assert!(mem.boot_rom_mapped());
+        assert_eq!(mem.read_byte(0x0000), 0x42);
+        assert_eq!(mem.read_byte(0x00FF), 0x42);
+        assert_eq!(mem.read_byte(0x0100), 0x11);
+
+        // Zero writes leave it mapped
+        mem.write_byte(BOOT_REG, 0);
+        assert!(mem.boot_rom_mapped());
+        mem.write_byte(BOOT_REG, 1);
+        assert!(!mem.boot_rom_mapped());
+        assert_eq!(mem.read_byte(0x0000), 0x11);
+
+        // Every load starts with the boot ROM again until it is removed
+        mem.load_rom_data(&rom);
+        assert!(mem.boot_rom_mapped());
+        mem.set_boot_rom(&[]).unwrap();
+        mem.load_rom_data(&rom);
+        assert!(!mem.boot_rom_mapped());
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_cgb_boot_rom_picks_mode() {
+        let mut mem = Memory::new();
+        mem.set_model(Model::Cgb);
+        mem.set_boot_rom(&[0x42; BOOT_ROM_SIZE_CGB]).unwrap();
+
+        // Cartridge without CGB support
+        mem.load_rom_data(&[0x11; 0x8000]);
+        assert!(mem.cgb_mode());
+        assert_eq!(mem.read_byte(0x0150), 0x11);
+        assert_eq!(mem.read_byte(0x0200), 0x42);
+        assert_eq!(mem.read_byte(0x08FF), 0x42);
+        assert_eq!(mem.read_byte(0x0900), 0x11);
+
+        mem.write_byte(KEY0, 0x04);
+        mem.write_byte(BOOT_REG, 1);
+        assert!(!mem.cgb_mode());
+        assert_eq!(mem.read_byte(0x0200), 0x11);
+
+        // KEY0 is locked once the boot ROM is gone
+        mem.write_byte(KEY0, 0x80);
+        assert_eq!(mem.read_byte(KEY0), 0x04);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_dmg_boot_rom_on_cgb_keeps_header_mode() {
+        let mut mem = Memory::new();
+        mem.set_model(Model::Cgb);
+        mem.set_boot_rom(&[0x42; BOOT_ROM_SIZE]).unwrap();
+
+        mem.load_rom_data(&[0x11; 0x8000]);
+        assert!(!mem.cgb_mode());
+        mem.write_byte(BOOT_REG, 1);
+        assert!(!mem.cgb_mode());
+
+        let mut rom = [0x11; 0x8000];
+        rom[0x143] = 0x80;
+        mem.load_rom_data(&rom);
+        assert!(mem.cgb_mode());
+        mem.write_byte(BOOT_REG, 1);
+        assert!(mem.cgb_mode());
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_startup_differs_per_model() {
+        let mut rom = [0; 0x8000];
+        rom[0x104] = 0xCE; // first byte of the logo
+
+        let mut mem = Memory::new();
+        mem.load_rom_data(&rom);
+        mem.ram_startup();
+        assert_eq!(mem.read_byte(DIVIDER_REGISTER), 0xAB);
+        assert_eq!(mem.read_byte(INPUT_REGISTER), 0xCF);
+        assert_eq!(mem.read_byte(0xFF26), 0xF1);
+        assert_eq!(mem.read_byte(SERIAL_CONTROL), 0x7E);
+        // 0xC doubles to 0xF0 and 0xE to 0xFC on two rows each
+        assert_eq!(mem.read_byte(0x8010), 0xF0);
+        assert_eq!(mem.read_byte(0x8012), 0xF0);
+        assert_eq!(mem.read_byte(0x8014), 0xFC);
+        assert_eq!(mem.read_byte(0x8016), 0xFC);
+        assert_eq!(mem.read_byte(0x8190), 0x3C);
+        assert_eq!(mem.read_byte(0x9904), 0x01);
+        assert_eq!(mem.read_byte(0x992F), 0x18);
+        assert_eq!(mem.read_byte(0x9910), 0x19);
+
+        mem.set_model(Model::Dmg0);
+        mem.load_rom_data(&rom);
+        mem.ram_startup();
+        assert_eq!(mem.read_byte(DIVIDER_REGISTER), 0x18);
+
+        mem.set_model(Model::Sgb2);
+        mem.load_rom_data(&rom);
+        mem.ram_startup();
+        assert_eq!(mem.read_byte(0xFF26), 0xF0);
+        assert_eq!(mem.read_byte(0x8010), 0x00);
+
+        mem.set_model(Model::Cgb);
+        mem.load_rom_data(&rom);
+        mem.ram_startup();
+        assert_eq!(mem.read_byte(SERIAL_CONTROL), 0x7F);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_reload_with_boot_rom_clears_cgb_state() {
+        let mut mem = Memory::new();
+        mem.set_model(Model::Cgb);
+        mem.set_boot_rom(&[0x42; BOOT_ROM_SIZE_CGB]).unwrap();
+        let mut rom = [0x11; 0x8000];
+        rom[0x143] = 0x80;
+        mem.load_rom_data(&rom);
+        mem.write_byte(BOOT_REG, 1);
+
+        mem.write_byte(VBK, 1);
+        mem.write_byte(SVBK, 3);
+        mem.write_byte(KEY1, 1);
+        assert!(mem.switch_speed());
+        mem.write_byte(HDMA1, 0xC0);
+        mem.write_byte(HDMA3, 0x00);
+        mem.write_byte(HDMA5, 0x82);
+        assert_eq!(mem.read_byte(HDMA5), 0x01);
+
+        mem.load_rom_data(&rom);
+        assert!(mem.boot_rom_mapped());
+        assert!(!mem.double_speed());
+        assert_eq!(mem.take_dma_stall(), 0);
+        assert_eq!(mem.read_byte(VBK), 0xFE);
+        assert_eq!(mem.read_byte(SVBK), 0xF9);
+        assert_eq!(mem.read_byte(KEY1), 0x7E);
+        assert_eq!(mem.read_byte(HDMA5), 0xFF);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_startup_io_registers_per_model() {
+        let mut rom = [0; 0x8000];
+        rom[0x143] = 0x80;
+        // model, DIV, STAT, LY, DMA, KEY0
+        for (model, div, stat, ly, dma, key0) in [
+            (Model::Dmg0, 0x18, 0x81, 0x91, 0xFF, 0xFF),
+            (Model::Dmg, 0xAB, 0x85, 0x00, 0xFF, 0xFF),
+            (Model::Mgb, 0xAB, 0x85, 0x00, 0xFF, 0xFF),
+            (Model::Sgb, 0x00, 0x85, 0x00, 0xFF, 0xFF),
+            (Model::Sgb2, 0x00, 0x85, 0x00, 0xFF, 0xFF),
+            (Model::Cgb, 0x00, 0x85, 0x00, 0x00, 0x80),
+        ] {
+            let mut mem = Memory::new();
+            mem.set_model(model);
+            mem.load_rom_data(&rom);
+            mem.ram_startup();
+            assert_eq!(mem.read_byte(DIVIDER_REGISTER), div, "{model:?}");
+            assert_eq!(mem.read_byte(LCD_STATUS), stat, "{model:?}");
+            assert_eq!(mem.read_byte(CURRENT_SCANLINE), ly, "{model:?}");
+            assert_eq!(mem.read_byte(DMA_REG), dma, "{model:?}");
+            assert_eq!(mem.read_byte(KEY0), key0, "{model:?}");
+            // CGB cartridges get the CGB registers only on a CGB
+            let cgb_register = if model.is_cgb() { 0x7E } else { 0xFF };
+            assert_eq!(mem.read_byte(KEY1), cgb_register, "{model:?}");
+        }
+
+        // Compatibility mode on a CGB
+        let mut mem = Memory::new();
+        mem.set_model(Model::Cgb);
+        mem.load_rom_data(&[0; 0x8000]);
+        mem.ram_startup();
+        assert_eq!(mem.read_byte(KEY0), 0x04);
+        assert_eq!(mem.read_byte(KEY1), 0xFF);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_title_checksum_only_for_nintendo() {
+        let mut rom = [0; 0x8000];
+        rom[0x134..0x137].copy_from_slice(b"ABC");
+        let mut mem = Memory::new();
+
+        mem.load_rom_data(&rom);
+        assert_eq!(mem.title_checksum(), None);
+
+        rom[0x14B] = 0x01;
+        mem.load_rom_data(&rom);
+        assert_eq!(mem.title_checksum(), Some(0xC6));
+
+        rom[0x14B] = 0x33;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        mem.load_rom_data(&rom);
+        assert_eq!(mem.title_checksum(), Some(0xC6));
+    }
 }
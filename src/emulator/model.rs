@@ -0,0 +1,57 @@
+//! Hardware models the emulator can run as
+
+/// The Game Boy hardware being emulated
+///
+/// Each model's boot ROM leaves the CPU registers, I/O registers, DIV and
+/// VRAM in a slightly different state, and some games check register A at
+/// 0x0100 to find out what they are running on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    /// Early Game Boy with the original revision of the boot ROM
+    Dmg0,
+    /// Original Game Boy
+    #[default]
+    Dmg,
+    /// Game Boy Pocket and Game Boy Light
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    /// Super Game Boy 2
+    Sgb2,
+    /// Game Boy Color
+    ///
+    /// Cartridges without CGB support (header byte 0x143) run in the DMG
+    /// compatibility mode, the same as they would on real hardware, and start
+    /// with the registers the CGB boot ROM leaves for that mode.
+    Cgb,
+}
+
+impl Model {
+    /// Every model, in the order they were released
+    pub const ALL: [(&'static str, Model); 6] = [
+        ("dmg0", Model::Dmg0),
+        ("dmg", Model::Dmg),
+        ("mgb", Model::Mgb),
+        ("sgb", Model::Sgb),
+        ("sgb2", Model::Sgb2),
+        ("cgb", Model::Cgb),
+    ];
+
+    /// Looks up a model by the short name listed in `ALL`
+    pub fn from_name(name: &str) -> Option<Model> {
+        Model::ALL
+            .iter()
+            .find(|(model, _)| model.eq_ignore_ascii_case(name))
+            .map(|(_, model)| *model)
+    }
+
+    /// Whether the model has the Game Boy Color hardware
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb)
+    }
+
+    /// Whether the model is one of the Super Game Boys
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+}
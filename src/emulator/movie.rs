@@ -0,0 +1,123 @@
+//! Recorded joypad input that can be played back frame for frame
+use std::vec::Vec;
+
+use crate::emulator::model::Model;
+
+/// Identifies a movie file
+const MAGIC: &[u8; 4] = b"RBGM";
+/// Bumped whenever the file layout changes
+const VERSION: u8 = 1;
+/// Magic, version, model, ROM checksum and frame count
+const HEADER_SIZE: usize = 4 + 1 + 1 + 4 + 4;
+
+/// Joypad input for every frame since power on
+///
+/// Each frame is one byte of buttons held down as the frame starts, with a
+/// bit each for A, B, Select, Start, Right, Left, Up and Down from bit 0.
+/// Playing a movie back on the same model and ROM gives the same frames as
+/// when it was recorded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// Model the movie was recorded on
+    pub model: Model,
+    /// CRC-32 of the ROM the movie was recorded with
+    pub rom_crc: u32,
+    /// Buttons held for each frame
+    pub frames: Vec<u8>,
+}
+
+impl Movie {
+    /// An empty movie for a model and ROM
+    pub fn new(model: Model, rom_crc: u32) -> Self {
+        Movie {
+            model,
+            rom_crc,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Encodes the movie as a file
+    ///
+    /// The header holds "RBGM", a version byte, the model, then the ROM
+    /// checksum and frame count as little endian `u32`s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.frames.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(model_id(self.model));
+        bytes.extend_from_slice(&self.rom_crc.to_le_bytes());
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.frames);
+        bytes
+    }
+
+    /// Decodes a movie written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, &'static str> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err("Not a movie file");
+        }
+        if bytes[4] != VERSION {
+            return Err("Unsupported movie version");
+        }
+        let model = model_from_id(bytes[5]).ok_or("Unknown model in movie")?;
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let len = word(10) as usize;
+        let frames = &bytes[HEADER_SIZE..];
+        if frames.len() != len {
+            return Err("Movie is truncated");
+        }
+        Ok(Movie {
+            model,
+            rom_crc: word(6),
+            frames: frames.to_vec(),
+        })
+    }
+
+    /// Reads a movie file from disk
+    pub fn load(path: &str) -> Result<Movie, std::string::String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        Movie::from_bytes(&bytes).map_err(|e| e.to_string())
+    }
+
+    /// Writes the movie to disk
+    pub fn save(&self, path: &str) -> Result<(), std::string::String> {
+        std::fs::write(path, self.to_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+const MODELS: [Model; 6] = [
+    Model::Dmg0,
+    Model::Dmg,
+    Model::Mgb,
+    Model::Sgb,
+    Model::Sgb2,
+    Model::Cgb,
+];
+
+fn model_id(model: Model) -> u8 {
+    MODELS.iter().position(|m| *m == model).unwrap_or_default() as u8
+}
+
+fn model_from_id(id: u8) -> Option<Model> {
+    MODELS.get(id as usize).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_movie_round_trip() {
+        let mut movie = Movie::new(Model::Cgb, 0x1234_5678);
+        movie.frames.extend_from_slice(&[0, 1, 0x80, 0xFF]);
+        let bytes = movie.to_bytes();
+        assert_eq!(&bytes[..6], b"RBGM\x01\x05");
+        assert_eq!(Movie::from_bytes(&bytes), Ok(movie));
+
+        assert!(Movie::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Movie::from_bytes(b"RBGX").is_err());
+    }
+}
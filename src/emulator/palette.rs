@@ -0,0 +1,279 @@
+//! Colors used to draw the DMG shades
+
+/// RGB for the four shades of one layer, lightest first
+pub type Shades = [[u8; 3]; 4];
+
+/// RGB colors the four DMG shades are drawn with
+///
+/// The background (and window) and the two sprite palettes selected by OBP0
+/// and OBP1 each get their own colors. Only used for DMG style rendering, CGB
+/// games pick their own colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// Background and window colors
+    pub bg: Shades,
+    /// Colors for sprites using OBP0
+    pub obj0: Shades,
+    /// Colors for sprites using OBP1
+    pub obj1: Shades,
+}
+
+const fn rgb(color: u32) -> [u8; 3] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+}
+
+const fn shades(colors: [u32; 4]) -> Shades {
+    [
+        rgb(colors[0]),
+        rgb(colors[1]),
+        rgb(colors[2]),
+        rgb(colors[3]),
+    ]
+}
+
+impl Palette {
+    /// Plain grays, what the emulator has always drawn with
+    pub const GRAYSCALE: Palette =
+        Palette::uniform(shades([0xFFFFFF, 0xCCCCCC, 0x777777, 0x000000]));
+
+    /// The green tint of the original Game Boy screen
+    pub const DMG_GREEN: Palette =
+        Palette::uniform(shades([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]));
+
+    /// The gray-green screen of the Game Boy Pocket
+    pub const POCKET: Palette = Palette::uniform(shades([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]));
+
+    /// The blue-green backlight of the Game Boy Light
+    pub const LIGHT: Palette = Palette::uniform(shades([0x00B581, 0x009A71, 0x00694A, 0x004F3B]));
+
+    /// All of the fixed presets along with a short name for each
+    pub const PRESETS: [(&'static str, Palette); 4] = [
+        ("grayscale", Palette::GRAYSCALE),
+        ("green", Palette::DMG_GREEN),
+        ("pocket", Palette::POCKET),
+        ("light", Palette::LIGHT),
+    ];
+
+    /// Creates a palette with separate background and sprite colors
+    pub const fn new(bg: Shades, obj0: Shades, obj1: Shades) -> Self {
+        Palette { bg, obj0, obj1 }
+    }
+
+    /// Creates a palette that uses the same colors for every layer
+    pub const fn uniform(shades: Shades) -> Self {
+        Palette::new(shades, shades, shades)
+    }
+
+    /// Looks up one of the presets by the name listed in `PRESETS`
+    pub fn from_name(name: &str) -> Option<Palette> {
+        Palette::PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(_, palette)| *palette)
+    }
+
+    /// The colors the CGB boot ROM gives an older game
+    ///
+    /// The boot ROM only recognises games published by Nintendo, picking them
+    /// out by the sum of their title bytes and, where two titles share a sum, the
+    /// fourth letter of the title. Everything else gets the default green and
+    /// blue palette.
+    pub fn cgb_compat(title_checksum: Option<u8>, fourth_letter: u8) -> Palette {
+        let index = title_checksum.and_then(|checksum| {
+            TITLE_CHECKSUMS.iter().enumerate().position(|(i, sum)| {
+                *sum == checksum
+                    && (i < FIRST_SHARED_CHECKSUM
+                        || FOURTH_LETTERS[i - FIRST_SHARED_CHECKSUM] == fourth_letter)
+            })
+        });
+        // The top bit of the ID is a flag kept next to the combination, the
+        // colors only depend on the low bits
+        let id = PALETTE_IDS[index.unwrap_or(0)] & 0x7F;
+        let [obj0, obj1, bg] = COMBINATIONS[usize::from(id)];
+        Palette::new(compat_shades(bg), compat_shades(obj0), compat_shades(obj1))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::GRAYSCALE
+    }
+}
+
+/// Reads four RGB555 colors from `COMPAT_COLORS`, starting at `offset`
+fn compat_shades(offset: u8) -> Shades {
+    let offset = usize::from(offset);
+    let mut shades = [[0; 3]; 4];
+    for (shade, color) in shades.iter_mut().zip(&COMPAT_COLORS[offset..offset + 4]) {
+        for (channel, shift) in shade.iter_mut().zip([0, 5, 10]) {
+            let level = u32::from((color >> shift) & 0x1F);
+            *channel = ((level * 255 + 15) / 31) as u8;
+        }
+    }
+    shades
+}
+
+/// Sums of the title bytes of the games the boot ROM knows
+///
+/// The entries from `FIRST_SHARED_CHECKSUM` on are sums that more than one
+/// title adds up to, so they only match along with their letter in
+/// `FOURTH_LETTERS`.
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+    0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+/// Index of the first checksum in `TITLE_CHECKSUMS` that needs a letter
+const FIRST_SHARED_CHECKSUM: usize = 65;
+
+/// Fourth title letter for each of the shared checksums
+const FOURTH_LETTERS: [u8; 29] = *b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// Colors picked for each entry of `TITLE_CHECKSUMS`
+///
+/// The low seven bits index `COMBINATIONS`, the top bit is a flag the boot ROM
+/// stores alongside.
+const PALETTE_IDS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 0x87, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5,
+    29, 5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 0x9A, 42, 30, 41, 34, 34, 5,
+    42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 32, 25, 6, 22, 12, 36, 11, 39,
+    18, 39, 24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// Offsets into `COMPAT_COLORS` of the OBJ0, OBJ1 and background colors
+///
+/// Most start on a palette, but a few begin part way into one and borrow colors
+/// from the next.
+const COMBINATIONS: [[u8; 3]; 51] = [
+    [4 * 4, 4 * 4, 29 * 4],
+    [18 * 4, 18 * 4, 18 * 4],
+    [20 * 4, 20 * 4, 20 * 4],
+    [24 * 4, 24 * 4, 24 * 4],
+    [9 * 4, 9 * 4, 9 * 4],
+    [0, 0, 0],
+    [27 * 4, 27 * 4, 27 * 4],
+    [5 * 4, 5 * 4, 5 * 4],
+    [12 * 4, 12 * 4, 12 * 4],
+    [26 * 4, 26 * 4, 26 * 4],
+    [16 * 4, 8 * 4, 8 * 4],
+    [4 * 4, 28 * 4, 28 * 4],
+    [4 * 4, 2 * 4, 2 * 4],
+    [3 * 4, 4 * 4, 4 * 4],
+    [4 * 4, 29 * 4, 29 * 4],
+    [28 * 4, 4 * 4, 28 * 4],
+    [2 * 4, 17 * 4, 2 * 4],
+    [16 * 4, 16 * 4, 8 * 4],
+    [4 * 4, 4 * 4, 7 * 4],
+    [4 * 4, 4 * 4, 18 * 4],
+    [4 * 4, 4 * 4, 20 * 4],
+    [19 * 4, 19 * 4, 9 * 4],
+    [4 * 4 - 1, 4 * 4 - 1, 11 * 4],
+    [17 * 4, 17 * 4, 2 * 4],
+    [4 * 4, 4 * 4, 2 * 4],
+    [4 * 4, 4 * 4, 3 * 4],
+    [28 * 4, 28 * 4, 0],
+    [3 * 4, 3 * 4, 0],
+    [0, 0, 4],
+    [18 * 4, 22 * 4, 18 * 4],
+    [20 * 4, 22 * 4, 20 * 4],
+    [24 * 4, 22 * 4, 24 * 4],
+    [16 * 4, 22 * 4, 8 * 4],
+    [17 * 4, 4 * 4, 13 * 4],
+    [28 * 4 - 1, 0, 14 * 4],
+    [28 * 4 - 1, 4 * 4, 15 * 4],
+    [19 * 4, 22 * 4, 9 * 4],
+    [16 * 4, 28 * 4, 10 * 4],
+    [4 * 4, 23 * 4, 28 * 4],
+    [17 * 4, 22 * 4, 2 * 4],
+    [4 * 4, 0, 2 * 4],
+    [4 * 4, 28 * 4, 3 * 4],
+    [28 * 4, 3 * 4, 0],
+    [3 * 4, 28 * 4, 4 * 4],
+    [21 * 4, 28 * 4, 4 * 4],
+    [3 * 4, 28 * 4, 0],
+    [25 * 4, 3 * 4, 28 * 4],
+    [0, 28 * 4, 8 * 4],
+    [4 * 4, 3 * 4, 28 * 4],
+    [28 * 4, 3 * 4, 6 * 4],
+    [4 * 4, 28 * 4, 29 * 4],
+];
+
+/// The boot ROM's palettes as RGB555, four colors each
+const COMPAT_COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB, 0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, 0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000, 0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000, 0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, 0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF, 0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000, 0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120, 0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000, 0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF, 0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_from_name() {
+        assert_eq!(Palette::from_name("Pocket"), Some(Palette::POCKET));
+        assert_eq!(Palette::from_name("sepia"), None);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_cgb_compat_lookup() {
+        let default = Palette::new(
+            shades([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]),
+            shades([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]),
+            shades([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]),
+        );
+
+        // TETRIS
+        let tetris = Palette::cgb_compat(Some(0xDB), b'R');
+        assert_eq!(
+            tetris,
+            Palette::uniform(shades([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000]))
+        );
+
+        // ZELDA
+        let zelda = Palette::cgb_compat(Some(0x70), b'D');
+        assert_eq!(zelda.bg, shades([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]));
+        assert_eq!(zelda.obj0, shades([0xFFFFFF, 0x00FF00, 0x318400, 0x004A00]));
+        assert_eq!(zelda.obj1, shades([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]));
+
+        // METROID2 and SUPER MARIOLAND share a checksum, told apart by letter
+        let metroid = Palette::cgb_compat(Some(0x46), b'R');
+        assert_eq!(metroid.bg, shades([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000]));
+        assert_eq!(
+            metroid.obj0,
+            shades([0xFFFF00, 0xFF0000, 0x630000, 0x000000])
+        );
+        assert_eq!(
+            metroid.obj1,
+            shades([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000])
+        );
+        let mario = Palette::cgb_compat(Some(0x46), b'E');
+        assert_eq!(mario.bg, shades([0xFFFFFF, 0xADAD84, 0x42737B, 0x000000]));
+        assert_eq!(mario.obj0[1], [0xFF, 0x73, 0x00]);
+        assert_eq!(Palette::cgb_compat(Some(0x46), b'X'), default);
+
+        // POKEMON RED and POKEMON BLUE
+        let red = Palette::cgb_compat(Some(0x14), b'E');
+        assert_eq!(red.obj0, shades([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000]));
+        let blue = Palette::cgb_compat(Some(0x61), b'E');
+        assert_eq!(blue.bg, metroid.bg);
+
+        // Games not published by Nintendo are never looked up
+        assert_eq!(Palette::cgb_compat(None, b'R'), default);
+        assert_eq!(Palette::cgb_compat(Some(0x12), b'R'), default);
+    }
+}
@@ -0,0 +1,203 @@
+//! PNG screenshots of the display buffer
+use std::{string::String, vec::Vec};
+
+use crate::emulator::checksum::crc32_update;
+use crate::emulator::config::PixelFormat;
+
+pub(crate) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// Largest block deflate can store without compressing
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// Encodes 8 bit RGB pixels, row by row from the top left, as a PNG
+///
+/// The image data is stored without compression, which keeps the encoder
+/// small. A Game Boy screen comes out at about 70KB.
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let mut png = Vec::with_capacity(rgb.len() + height as usize + 128);
+    png.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &header(width, height));
+    write_chunk(&mut png, b"IDAT", &image_data(width, height, rgb));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// The IHDR chunk's contents for an RGB image
+pub(crate) fn header(width: u32, height: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, no filtering, not interlaced
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    header
+}
+
+/// The zlib stream of an image's rows, as stored in IDAT chunks
+pub(crate) fn image_data(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let row_len = width as usize * 3;
+    assert_eq!(
+        rgb.len(),
+        row_len * height as usize,
+        "RGB data doesn't match the image size"
+    );
+
+    // Each row starts with the filter type, 0 for none
+    let mut raw = Vec::with_capacity((row_len + 1) * height as usize);
+    for row in rgb.chunks_exact(row_len.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    zlib_stored(&raw)
+}
+
+/// Appends a chunk with its length and CRC
+pub(crate) fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = !crc32_update(crc32_update(!0, kind), data);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums can go this far before they need reducing without overflowing
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Converts a display buffer in any format to 8 bit RGB
+///
+/// Shade indexes become grays, lightest for 0.
+pub(crate) fn to_rgb(buffer: &[u8], format: PixelFormat) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(buffer.len() / format.bytes_per_pixel() * 3);
+    for pixel in buffer.chunks_exact(format.bytes_per_pixel()) {
+        let (r, g, b) = match format {
+            PixelFormat::Rgb888 | PixelFormat::Rgba8888 => (pixel[0], pixel[1], pixel[2]),
+            PixelFormat::Bgra8888 => (pixel[2], pixel[1], pixel[0]),
+            PixelFormat::Rgb565 => {
+                let color = u16::from_le_bytes([pixel[0], pixel[1]]);
+                let r = (color >> 11) as u8 & 0x1F;
+                let g = (color >> 5) as u8 & 0x3F;
+                let b = color as u8 & 0x1F;
+                (
+                    (r << 3) | (r >> 2),
+                    (g << 2) | (g >> 4),
+                    (b << 3) | (b >> 2),
+                )
+            }
+            PixelFormat::ShadeIndex => {
+                let gray = 0xFF - (pixel[0] & 0x3) * 0x55;
+                (gray, gray, gray)
+            }
+        };
+        rgb.extend_from_slice(&[r, g, b]);
+    }
+    rgb
+}
+
+/// A file name with the current UTC date and time, such as
+/// `rbgb-2024-05-01-153000.png` for a prefix of `rbgb` and extension of `png`
+pub fn timestamped_name(prefix: &str, extension: &str) -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    format!("{prefix}-{}.{extension}", timestamp(seconds))
+}
+
+// Turns seconds since 1970 into `YYYY-MM-DD-HHMMSS`, using Howard Hinnant's
+// days to civil date algorithm
+fn timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}-{:02}{:02}{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::checksum::crc32;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_encode_png() {
+        let png = encode_png(2, 1, &[0xFF, 0, 0, 0, 0, 0xFF]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(png[29..33], crc32(&png[12..29]).to_be_bytes());
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+
+        // Both rows of pixels and their filter bytes are stored as they are
+        let idat = &png[33..png.len() - 12];
+        assert_eq!(&idat[4..8], b"IDAT");
+        assert_eq!(idat[10..15], [1, 7, 0, !7, 0xFF]);
+        assert_eq!(idat[15..22], [0, 0xFF, 0, 0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        let long = [0xFF; 100_000];
+        let slow = long.iter().fold((1u64, 0u64), |(a, b), byte| {
+            let a = (a + *byte as u64) % 65521;
+            (a, (b + a) % 65521)
+        });
+        assert_eq!(adler32(&long), ((slow.1 << 16) | slow.0) as u32);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "1970-01-01-000000");
+        assert_eq!(timestamp(1_700_000_000), "2023-11-14-221320");
+        assert_eq!(timestamp(951_825_600), "2000-02-29-120000");
+    }
+}
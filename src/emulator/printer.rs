@@ -0,0 +1,466 @@
+//! Game Boy Printer, plugged into the serial port
+use crate::types::Byte;
+
+/// Bytes of tile data the printer can hold, 8KB like the real one
+pub const PRINTER_RAM: usize = 0x2000;
+/// Width of a printed image in pixels, the same as the screen
+pub const PRINT_WIDTH: usize = 160;
+#[cfg(feature = "std")]
+/// Pixel rows of blank paper fed for each unit of margin
+pub const FEED_ROWS: usize = 8;
+
+/// Most data bytes in one packet, two rows of tiles
+const MAX_PACKET: usize = 0x280;
+/// A row of 20 tiles, 16 bytes each
+const TILE_ROW: usize = PRINT_WIDTH / 8 * 16;
+/// How long the printer stays busy for each row of tiles printed, about a
+/// second for a whole screen
+const BUSY_CYCLES_PER_ROW: u32 = 0x40000;
+
+const COMMAND_INIT: Byte = 0x01;
+const COMMAND_PRINT: Byte = 0x02;
+const COMMAND_DATA: Byte = 0x04;
+const COMMAND_STATUS: Byte = 0x0F;
+
+/// Sent back in place of the first of the two bytes after the checksum
+const DEVICE_ID: Byte = 0x81;
+
+const STATUS_CHECKSUM_ERROR: Byte = 0x01;
+const STATUS_BUSY: Byte = 0x02;
+const STATUS_READY: Byte = 0x04;
+const STATUS_UNPROCESSED: Byte = 0x08;
+const STATUS_PACKET_ERROR: Byte = 0x10;
+
+// Where the printer is in a packet: two magic bytes, command, compression
+// flag, data length, data, checksum, then two bytes for its replies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Magic,
+    Magic2,
+    Command,
+    Compression,
+    Length,
+    Length2,
+    Data,
+    Checksum,
+    Checksum2,
+    DeviceId,
+    Status,
+}
+
+/// An image the printer has printed
+///
+/// The image data is in the same 2 bits per pixel tile format as VRAM, rows
+/// of 20 tiles from the top left.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Print {
+    data: [Byte; PRINTER_RAM],
+    len: usize,
+    /// Copies to print, 0 only feeds the paper
+    pub sheets: Byte,
+    /// Units of blank paper fed before printing, each eight pixel rows
+    pub margin_before: Byte,
+    /// Units of blank paper fed after printing, 0 leaves the paper where it
+    /// is so the next print carries on from this one
+    pub margin_after: Byte,
+    /// Shade for each color, 2 bits each from color 0 like BGP
+    pub palette: Byte,
+    /// How dark to print, 0x40 is normal. Not used for the image.
+    pub exposure: Byte,
+}
+
+impl Print {
+    /// The tile data that was printed
+    pub fn data(&self) -> &[Byte] {
+        &self.data[..self.len]
+    }
+
+    /// Height of the image in pixels, only whole rows of tiles are printed
+    pub fn height(&self) -> usize {
+        self.len / TILE_ROW * 8
+    }
+
+    /// The shade of a pixel after the palette, 0 for white to 3 for black
+    pub fn shade(&self, x: usize, y: usize) -> Byte {
+        let tile = y / 8 * (PRINT_WIDTH / 8) + x / 8;
+        let offset = tile * 16 + y % 8 * 2;
+        let bit = 7 - x % 8;
+        let color = (((self.data[offset + 1] >> bit) & 1) << 1) | ((self.data[offset] >> bit) & 1);
+        // Games that don't set a palette still get the usual one
+        let palette = match self.palette {
+            0 => 0xE4,
+            palette => palette,
+        };
+        (palette >> (color * 2)) & 0x3
+    }
+}
+
+/// The printer's side of the link cable
+///
+/// The Game Boy sends packets with the clock on its side, and the printer
+/// answers with its ID and status in the last two bytes of each one.
+pub struct Printer {
+    stage: Stage,
+    command: Byte,
+    compressed: bool,
+    length: usize,
+    received: usize,
+    packet: [Byte; MAX_PACKET],
+    checksum: u16,
+    expected_checksum: u16,
+    status: Byte,
+    ram: [Byte; PRINTER_RAM],
+    ram_len: usize,
+    busy_cycles: u32,
+    printed: Option<Print>,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Printer {
+            stage: Stage::Magic,
+            command: 0,
+            compressed: false,
+            length: 0,
+            received: 0,
+            packet: [0; MAX_PACKET],
+            checksum: 0,
+            expected_checksum: 0,
+            status: 0,
+            ram: [0; PRINTER_RAM],
+            ram_len: 0,
+            busy_cycles: 0,
+            printed: None,
+        }
+    }
+
+    /// Takes a byte from the Game Boy, returning the one sent back at the
+    /// same time
+    pub fn exchange(&mut self, byte: Byte) -> Byte {
+        let reply = match self.stage {
+            Stage::DeviceId => DEVICE_ID,
+            Stage::Status => self.status,
+            _ => 0,
+        };
+
+        self.stage = match self.stage {
+            // Anything but the magic bytes is ignored between packets
+            Stage::Magic if byte == 0x88 => Stage::Magic2,
+            Stage::Magic => Stage::Magic,
+            Stage::Magic2 if byte == 0x33 => Stage::Command,
+            Stage::Magic2 => Stage::Magic,
+            Stage::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                Stage::Compression
+            }
+            Stage::Compression => {
+                self.compressed = byte & 0x1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::Length
+            }
+            Stage::Length => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::Length2
+            }
+            Stage::Length2 => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.received = 0;
+                if self.length == 0 {
+                    Stage::Checksum
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::Data => {
+                if let Some(slot) = self.packet.get_mut(self.received) {
+                    *slot = byte;
+                }
+                self.received += 1;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.received == self.length {
+                    Stage::Checksum
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::Checksum => {
+                self.expected_checksum = byte as u16;
+                Stage::Checksum2
+            }
+            Stage::Checksum2 => {
+                self.expected_checksum |= (byte as u16) << 8;
+                self.run_command();
+                Stage::DeviceId
+            }
+            Stage::DeviceId => Stage::Status,
+            Stage::Status => Stage::Magic,
+        };
+        reply
+    }
+
+    /// Counts down the time taken to print
+    pub fn tick(&mut self, cycles: u32) {
+        if self.busy_cycles > 0 {
+            self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+            if self.busy_cycles == 0 {
+                self.status &= !STATUS_BUSY;
+            }
+        }
+    }
+
+    /// Takes the last print, if there's been one since last time
+    pub fn take_print(&mut self) -> Option<Print> {
+        self.printed.take()
+    }
+
+    fn run_command(&mut self) {
+        if self.checksum != self.expected_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !(STATUS_CHECKSUM_ERROR | STATUS_PACKET_ERROR);
+        if self.length > MAX_PACKET {
+            self.status |= STATUS_PACKET_ERROR;
+            return;
+        }
+
+        match self.command {
+            COMMAND_INIT => {
+                self.ram_len = 0;
+                self.status = 0;
+                self.busy_cycles = 0;
+            }
+            COMMAND_DATA if self.length == 0 => self.status |= STATUS_READY,
+            COMMAND_DATA => {
+                self.store_data();
+                self.status |= STATUS_UNPROCESSED;
+            }
+            COMMAND_PRINT if self.length == 4 => self.print(),
+            COMMAND_STATUS => {}
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    // Copies a DATA packet into RAM, undoing its run length encoding
+    fn store_data(&mut self) {
+        let packet = &self.packet[..self.length];
+        let out = |byte: Byte, ram: &mut [Byte; PRINTER_RAM], len: &mut usize| {
+            if let Some(slot) = ram.get_mut(*len) {
+                *slot = byte;
+                *len += 1;
+            }
+        };
+        if !self.compressed {
+            for byte in packet {
+                out(*byte, &mut self.ram, &mut self.ram_len);
+            }
+            return;
+        }
+
+        // A control byte with the top bit set repeats the next byte its low
+        // bits + 2 times, otherwise the next low bits + 1 bytes are copied
+        let mut i = 0;
+        while i < packet.len() {
+            let control = packet[i];
+            i += 1;
+            if control & 0x80 != 0 {
+                let Some(&byte) = packet.get(i) else {
+                    break;
+                };
+                for _ in 0..(control & 0x7F) as usize + 2 {
+                    out(byte, &mut self.ram, &mut self.ram_len);
+                }
+                i += 1;
+            } else {
+                let end = (i + control as usize + 1).min(packet.len());
+                for byte in &packet[i..end] {
+                    out(*byte, &mut self.ram, &mut self.ram_len);
+                }
+                i = end;
+            }
+        }
+    }
+
+    fn print(&mut self) {
+        let [sheets, margins, palette, exposure, ..] = self.packet;
+        let print = Print {
+            data: self.ram,
+            len: self.ram_len,
+            sheets,
+            margin_before: margins >> 4,
+            margin_after: margins & 0xF,
+            palette,
+            exposure,
+        };
+        self.busy_cycles = (print.height() / 8).max(1) as u32 * BUSY_CYCLES_PER_ROW;
+        self.status = STATUS_BUSY;
+        self.ram_len = 0;
+        self.printed = Some(print);
+    }
+}
+
+#[cfg(feature = "std")]
+/// Prints joined up into pages, the way they come out of the printer
+///
+/// Prints without a margin after them carry straight on into the next one,
+/// which is how games print images taller than the printer's RAM.
+#[derive(Clone, Debug, Default)]
+pub struct Paper {
+    shades: std::vec::Vec<Byte>,
+    printed: bool,
+}
+
+#[cfg(feature = "std")]
+impl Paper {
+    /// Blank paper
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a print and its margins to the page
+    ///
+    /// Returns true once the paper has been fed on after the print, so the
+    /// page is finished.
+    pub fn add(&mut self, print: &Print) -> bool {
+        self.feed(print.margin_before);
+        if print.sheets > 0 {
+            for y in 0..print.height() {
+                self.shades
+                    .extend((0..PRINT_WIDTH).map(|x| print.shade(x, y)));
+            }
+            self.printed |= print.height() > 0;
+        }
+        self.feed(print.margin_after);
+        print.margin_after > 0
+    }
+
+    /// Whether nothing but blank paper has come out
+    pub fn is_empty(&self) -> bool {
+        !self.printed
+    }
+
+    /// Height of the page in pixels
+    pub fn height(&self) -> usize {
+        self.shades.len() / PRINT_WIDTH
+    }
+
+    /// Encodes the page as a grayscale PNG
+    pub fn to_png(&self) -> std::vec::Vec<u8> {
+        use crate::emulator::{config::PixelFormat, png};
+        let rgb = png::to_rgb(&self.shades, PixelFormat::ShadeIndex);
+        png::encode_png(PRINT_WIDTH as u32, self.height() as u32, &rgb)
+    }
+
+    /// Tears the page off, leaving blank paper
+    pub fn clear(&mut self) {
+        self.shades.clear();
+        self.printed = false;
+    }
+
+    fn feed(&mut self, units: Byte) {
+        let rows = units as usize * FEED_ROWS;
+        self.shades
+            .resize(self.shades.len() + rows * PRINT_WIDTH, 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    // Sends a whole packet, returning the device ID and status bytes
+    fn send(printer: &mut Printer, command: Byte, compressed: bool, data: &[Byte]) -> (Byte, Byte) {
+        let mut body = std::vec![command, compressed as Byte];
+        body.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        body.extend_from_slice(data);
+        let checksum = body
+            .iter()
+            .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+
+        for byte in [0x88, 0x33]
+            .iter()
+            .chain(&body)
+            .chain(&checksum.to_le_bytes())
+        {
+            assert_eq!(printer.exchange(*byte), 0);
+        }
+        (printer.exchange(0), printer.exchange(0))
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_packet_replies() {
+        let mut printer = Printer::new();
+        assert_eq!(send(&mut printer, COMMAND_INIT, false, &[]), (DEVICE_ID, 0));
+        // The status at the end of a packet already has its effects
+        assert_eq!(
+            send(&mut printer, COMMAND_DATA, false, &[0; 0x280]),
+            (DEVICE_ID, STATUS_UNPROCESSED)
+        );
+
+        // A bad checksum is reported and the packet ignored
+        for byte in [0x88, 0x33, COMMAND_INIT, 0, 0, 0, 0xFF, 0xFF] {
+            printer.exchange(byte);
+        }
+        assert_eq!(printer.exchange(0), DEVICE_ID);
+        assert_eq!(
+            printer.exchange(0),
+            STATUS_UNPROCESSED | STATUS_CHECKSUM_ERROR
+        );
+        assert_eq!(
+            send(&mut printer, COMMAND_STATUS, false, &[]).1,
+            STATUS_UNPROCESSED
+        );
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_print() {
+        let mut printer = Printer::new();
+        send(&mut printer, COMMAND_INIT, false, &[]);
+        // A row of tiles with every pixel color 1, then one with color 2
+        send(&mut printer, COMMAND_DATA, true, &[0xFF, 0xFF]);
+        send(&mut printer, COMMAND_DATA, false, &[]);
+        assert_eq!(printer.ram_len, 0x7F + 2);
+
+        let mut data = std::vec![0; TILE_ROW];
+        data.extend((0..TILE_ROW).map(|i| if i % 2 == 0 { 0 } else { 0xFF }));
+        send(&mut printer, COMMAND_INIT, false, &[]);
+        send(&mut printer, COMMAND_DATA, false, &data);
+        send(&mut printer, COMMAND_DATA, false, &[]);
+        assert_eq!(
+            send(&mut printer, COMMAND_STATUS, false, &[]).1,
+            STATUS_READY | STATUS_UNPROCESSED
+        );
+        send(&mut printer, COMMAND_PRINT, false, &[1, 0x13, 0xE4, 0x40]);
+        assert_eq!(
+            send(&mut printer, COMMAND_STATUS, false, &[]).1,
+            STATUS_BUSY
+        );
+
+        let print = printer.take_print().unwrap();
+        assert_eq!(print.height(), 16);
+        assert_eq!((print.margin_before, print.margin_after), (1, 3));
+        assert_eq!(print.shade(0, 0), 0);
+        assert_eq!(print.shade(159, 15), 2);
+        assert!(printer.take_print().is_none());
+
+        printer.tick(2 * BUSY_CYCLES_PER_ROW);
+        assert_eq!(send(&mut printer, COMMAND_STATUS, false, &[]).1, 0);
+
+        let mut paper = Paper::new();
+        assert!(paper.add(&print));
+        assert_eq!(paper.height(), 16 + 4 * FEED_ROWS);
+        assert!(!paper.is_empty());
+    }
+}
@@ -0,0 +1,593 @@
+//! Video recordings of the display, as animated images or raw Y4M and WAV
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::emulator::Emulator;
+use crate::emulator::png::{self, SIGNATURE};
+
+/// CPU clock, the Game Boy runs 4194304 / 70224 frames a second
+const CLOCK_RATE: u64 = 4_194_304;
+/// Frame time for APNG, whose delays are 16 bit fractions of a second
+const APNG_DELAY: (u16, u16) = (1000, 59727);
+/// Where the acTL chunk is in an APNG, after the signature and IHDR
+const ACTL_OFFSET: u64 = 8 + 25;
+/// GIFs only get every other frame, few viewers keep up with 60 a second
+const GIF_FRAME_STEP: u64 = 2;
+/// Most LZW codes a GIF can use before the table has to start over
+const GIF_MAX_CODES: u16 = 4096;
+/// Samples a second in the WAV next to a Y4M recording
+const WAV_SAMPLE_RATE: u32 = 44_100;
+
+/// File formats the display can be recorded to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// Animated GIF at half the frame rate, small and plays anywhere
+    #[default]
+    Gif,
+    /// Animated PNG with every frame, lossless but large
+    Apng,
+    /// Uncompressed YUV4MPEG2 video with a WAV file of the same name next to
+    /// it, for muxing with a tool like ffmpeg
+    Y4m,
+}
+
+impl VideoFormat {
+    /// Every format, with the name each goes by
+    pub const ALL: [(&'static str, VideoFormat); 3] = [
+        ("gif", VideoFormat::Gif),
+        ("apng", VideoFormat::Apng),
+        ("y4m", VideoFormat::Y4m),
+    ];
+
+    /// Looks a format up by name, case insensitively
+    pub fn from_name(name: &str) -> Option<VideoFormat> {
+        Self::ALL
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, format)| *format)
+    }
+
+    /// Picks the format from a file's extension, `.png` being an APNG
+    pub fn from_path(path: &str) -> Option<VideoFormat> {
+        let (_, extension) = path.rsplit_once('.')?;
+        if extension.eq_ignore_ascii_case("png") {
+            return Some(VideoFormat::Apng);
+        }
+        Self::from_name(extension)
+    }
+
+    /// The extension files in this format are saved with
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Apng => "png",
+            VideoFormat::Y4m => "y4m",
+        }
+    }
+}
+
+/// Writes the frames it is given to a video file as they come
+///
+/// Frames are 160x144 8 bit RGB, see [`Emulator::record_frame`]. Sound isn't
+/// emulated yet, so the WAV alongside a Y4M recording is silence of the right
+/// length, ready to be replaced once there are samples to write.
+pub struct Recorder {
+    format: VideoFormat,
+    video: BufWriter<File>,
+    audio: Option<Wav>,
+    frames: u64,
+    // APNG chunks are numbered across fcTL and fdAT
+    sequence: u32,
+    // Centiseconds of GIF written so far
+    gif_time: u64,
+}
+
+impl Recorder {
+    /// Starts a recording at `path`
+    ///
+    /// Y4M recordings also create a WAV file at the same path with a `.wav`
+    /// extension.
+    ///
+    /// Returns a string I/O error if a file can't be created.
+    pub fn create(path: &str, format: VideoFormat) -> Result<Recorder, String> {
+        let mut video = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        let mut audio = None;
+        let (width, height) = (crate::SCREEN_WIDTH, crate::SCREEN_HEIGHT);
+        let header = match format {
+            VideoFormat::Gif => gif_header(width as u16, height as u16),
+            VideoFormat::Apng => {
+                let mut header = SIGNATURE.to_vec();
+                png::write_chunk(&mut header, b"IHDR", &png::header(width, height));
+                png::write_chunk(&mut header, b"acTL", &actl(0));
+                header
+            }
+            VideoFormat::Y4m => {
+                let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+                audio = Some(Wav::create(&format!("{stem}.wav"))?);
+                format!(
+                    "YUV4MPEG2 W{width} H{height} F{CLOCK_RATE}:{} Ip A1:1 C444\n",
+                    Emulator::FRAME_CYCLES
+                )
+                .into_bytes()
+            }
+        };
+        video.write_all(&header).map_err(|e| e.to_string())?;
+        Ok(Recorder {
+            format,
+            video,
+            audio,
+            frames: 0,
+            sequence: 0,
+            gif_time: 0,
+        })
+    }
+
+    /// The format being written
+    pub fn format(&self) -> VideoFormat {
+        self.format
+    }
+
+    /// Frames recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Adds the next frame of 160x144 8 bit RGB pixels
+    pub fn add_frame(&mut self, rgb: &[u8]) -> Result<(), String> {
+        let (width, height) = (crate::SCREEN_WIDTH, crate::SCREEN_HEIGHT);
+        if rgb.len() != (width * height * 3) as usize {
+            return Err("Frame doesn't match the screen size".to_string());
+        }
+
+        let frame = self.frames;
+        self.frames += 1;
+        let bytes = match self.format {
+            VideoFormat::Gif => {
+                if !frame.is_multiple_of(GIF_FRAME_STEP) {
+                    return Ok(());
+                }
+                // Rounding each frame's end keeps the total in step with the
+                // Game Boy even though single delays can't be exact
+                let end =
+                    (frame + GIF_FRAME_STEP) * 100 * Emulator::FRAME_CYCLES as u64 / CLOCK_RATE;
+                let delay = (end - self.gif_time) as u16;
+                self.gif_time = end;
+                gif_frame(width as u16, height as u16, rgb, delay)
+            }
+            VideoFormat::Apng => {
+                let mut bytes = Vec::new();
+                png::write_chunk(&mut bytes, b"fcTL", &self.fctl(width, height));
+                let data = png::image_data(width, height, rgb);
+                if frame == 0 {
+                    png::write_chunk(&mut bytes, b"IDAT", &data);
+                } else {
+                    let mut fdat = self.next_sequence().to_be_bytes().to_vec();
+                    fdat.extend_from_slice(&data);
+                    png::write_chunk(&mut bytes, b"fdAT", &fdat);
+                }
+                bytes
+            }
+            VideoFormat::Y4m => {
+                if let Some(audio) = &mut self.audio {
+                    audio.write_silence_until(self.frames)?;
+                }
+                let mut bytes = b"FRAME\n".to_vec();
+                bytes.extend_from_slice(&to_yuv444(rgb));
+                bytes
+            }
+        };
+        self.video.write_all(&bytes).map_err(|e| e.to_string())
+    }
+
+    /// Finishes off the files, returning how many frames were recorded
+    ///
+    /// Dropping a recorder without calling this leaves its files truncated. An
+    /// APNG has to hold at least one image, so one with no frames gets a blank
+    /// one.
+    pub fn finish(mut self) -> Result<u64, String> {
+        let frames = self.frames;
+        if self.format == VideoFormat::Apng && frames == 0 {
+            let (width, height) = (crate::SCREEN_WIDTH, crate::SCREEN_HEIGHT);
+            self.add_frame(&vec![0xFF; (width * height * 3) as usize])?;
+        }
+        match self.format {
+            VideoFormat::Gif => self.video.write_all(&[0x3B]),
+            VideoFormat::Apng => {
+                let mut end = Vec::new();
+                png::write_chunk(&mut end, b"IEND", &[]);
+                let mut actl_chunk = Vec::new();
+                png::write_chunk(&mut actl_chunk, b"acTL", &actl(self.frames as u32));
+                self.video
+                    .write_all(&end)
+                    .and_then(|_| self.video.seek(SeekFrom::Start(ACTL_OFFSET)))
+                    .and_then(|_| self.video.write_all(&actl_chunk))
+            }
+            VideoFormat::Y4m => Ok(()),
+        }
+        .and_then(|_| self.video.flush())
+        .map_err(|e| e.to_string())?;
+        if let Some(audio) = self.audio {
+            audio.finish()?;
+        }
+        Ok(frames)
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence += 1;
+        self.sequence - 1
+    }
+
+    // Frame control for the whole image, replacing what was there before
+    fn fctl(&mut self, width: u32, height: u32) -> Vec<u8> {
+        let mut fctl = self.next_sequence().to_be_bytes().to_vec();
+        fctl.extend_from_slice(&width.to_be_bytes());
+        fctl.extend_from_slice(&height.to_be_bytes());
+        fctl.extend_from_slice(&[0; 8]);
+        fctl.extend_from_slice(&APNG_DELAY.0.to_be_bytes());
+        fctl.extend_from_slice(&APNG_DELAY.1.to_be_bytes());
+        // No disposal, and the frame replaces the canvas rather than blending
+        fctl.extend_from_slice(&[0, 0]);
+        fctl
+    }
+}
+
+// Animation control, the frame count then 0 to loop forever
+fn actl(frames: u32) -> Vec<u8> {
+    let mut actl = frames.to_be_bytes().to_vec();
+    actl.extend_from_slice(&0u32.to_be_bytes());
+    actl
+}
+
+// The screen descriptor with no global colors, and the extension that makes
+// the animation loop
+fn gif_header(width: u16, height: u16) -> Vec<u8> {
+    let mut header = b"GIF89a".to_vec();
+    header.extend_from_slice(&width.to_le_bytes());
+    header.extend_from_slice(&height.to_le_bytes());
+    header.extend_from_slice(&[0, 0, 0]);
+    header.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+    header
+}
+
+// One full size image with its own color table and a delay in centiseconds
+fn gif_frame(width: u16, height: u16, rgb: &[u8], delay: u16) -> Vec<u8> {
+    let (palette, indexes) = index_colors(rgb);
+    // Color tables hold a power of two colors, at least 2
+    let table_bits = (usize::BITS - (palette.len() - 1).max(1).leading_zeros()) as u8;
+
+    let mut frame = vec![0x21, 0xF9, 4, 0];
+    frame.extend_from_slice(&delay.to_le_bytes());
+    frame.extend_from_slice(&[0, 0, 0x2C, 0, 0, 0, 0]);
+    frame.extend_from_slice(&width.to_le_bytes());
+    frame.extend_from_slice(&height.to_le_bytes());
+    frame.push(0x80 | (table_bits - 1));
+    for index in 0..1usize << table_bits {
+        frame.extend_from_slice(&palette.get(index).copied().unwrap_or_default());
+    }
+
+    let min_code_size = table_bits.max(2);
+    frame.push(min_code_size);
+    for block in lzw(&indexes, min_code_size).chunks(255) {
+        frame.push(block.len() as u8);
+        frame.extend_from_slice(block);
+    }
+    frame.push(0);
+    frame
+}
+
+// Splits a frame into its colors and an index into them for each pixel.
+// Game Boy Color frames can have more than the 256 colors a GIF allows, those
+// are cut down to 3 bits of red and green and 2 of blue.
+fn index_colors(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indexes = Vec::with_capacity(rgb.len() / 3);
+    for pixel in rgb.chunks_exact(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = *lookup.entry(color).or_insert_with(|| {
+            palette.push(color);
+            palette.len() - 1
+        });
+        if palette.len() > 256 {
+            return reduce_colors(rgb);
+        }
+        indexes.push(index as u8);
+    }
+    (palette, indexes)
+}
+
+fn reduce_colors(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let palette = (0..=255u8)
+        .map(|i| {
+            let scale = |bits: u8, max: u8| (bits as u16 * 255 / max as u16) as u8;
+            [scale(i >> 5, 7), scale((i >> 2) & 7, 7), scale(i & 3, 3)]
+        })
+        .collect();
+    let indexes = rgb
+        .chunks_exact(3)
+        .map(|pixel| (pixel[0] & 0xE0) | ((pixel[1] >> 3) & 0x1C) | (pixel[2] >> 6))
+        .collect();
+    (palette, indexes)
+}
+
+// GIF's variable length LZW, codes packed from the lowest bit up
+fn lzw(indexes: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut codes = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next = end + 1;
+
+    codes.write(clear, code_size);
+    let Some((&first, rest)) = indexes.split_first() else {
+        codes.write(end, code_size);
+        return codes.finish();
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        codes.write(prefix, code_size);
+        // Readers widen their codes a step behind, once the code about to be
+        // added no longer fits
+        if next >= 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+        if next < GIF_MAX_CODES {
+            table.insert((prefix, index), next);
+            next += 1;
+        } else {
+            codes.write(clear, code_size);
+            table.clear();
+            code_size = min_code_size + 1;
+            next = end + 1;
+        }
+        prefix = index as u16;
+    }
+    codes.write(prefix, code_size);
+    if next >= 1 << code_size && code_size < 12 {
+        code_size += 1;
+    }
+    codes.write(end, code_size);
+    codes.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.bits |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+// Full planes of Y, then Cb, then Cr, with BT.601's limited range integer
+// approximation
+fn to_yuv444(rgb: &[u8]) -> Vec<u8> {
+    let pixels = rgb.len() / 3;
+    let mut yuv = vec![0; pixels * 3];
+    for (i, pixel) in rgb.chunks_exact(3).enumerate() {
+        let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+        yuv[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        yuv[pixels + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        yuv[2 * pixels + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    yuv
+}
+
+// 16 bit stereo PCM, with the sizes filled in when it's finished
+struct Wav {
+    file: BufWriter<File>,
+    samples: u64,
+}
+
+impl Wav {
+    const CHANNELS: u16 = 2;
+    const BYTES_PER_SAMPLE: u16 = 2 * Self::CHANNELS;
+
+    fn create(path: &str) -> Result<Wav, String> {
+        let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        file.write_all(&wav_header(WAV_SAMPLE_RATE, 0))
+            .map_err(|e| e.to_string())?;
+        Ok(Wav { file, samples: 0 })
+    }
+
+    // Pads with silence up to the end of the given number of video frames
+    fn write_silence_until(&mut self, frames: u64) -> Result<(), String> {
+        let total = frames * WAV_SAMPLE_RATE as u64 * Emulator::FRAME_CYCLES as u64 / CLOCK_RATE;
+        let silence = vec![0; ((total - self.samples) * Self::BYTES_PER_SAMPLE as u64) as usize];
+        self.samples = total;
+        self.file.write_all(&silence).map_err(|e| e.to_string())
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        let data_len = (self.samples * Self::BYTES_PER_SAMPLE as u64) as u32;
+        self.file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.file.write_all(&wav_header(WAV_SAMPLE_RATE, data_len)))
+            .and_then(|_| self.file.flush())
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn wav_header(sample_rate: u32, data_len: u32) -> Vec<u8> {
+    let block_align = Wav::BYTES_PER_SAMPLE;
+    let mut header = b"RIFF".to_vec();
+    header.extend_from_slice(&(36 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // Integer PCM
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&Wav::CHANNELS.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    // Decodes GIF LZW the way a viewer would, to check the encoder against
+    fn unlzw(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1u16 << min_code_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            table.clear();
+            table.extend((0..clear).map(|i| vec![i as u8]));
+            table.extend([Vec::new(), Vec::new()]);
+        };
+        reset(&mut table);
+        let mut code_size = min_code_size + 1;
+        let (mut bits, mut count, mut bytes) = (0u32, 0u8, data.iter());
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        loop {
+            while count < code_size {
+                bits |= (*bytes.next().unwrap() as u32) << count;
+                count += 8;
+            }
+            let code = (bits & ((1 << code_size) - 1)) as u16;
+            bits >>= code_size;
+            count -= code_size;
+
+            if code == clear {
+                reset(&mut table);
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code as usize), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [previous.clone(), vec![previous[0]]].concat(),
+                (None, None) => panic!("Bad first code"),
+            };
+            if let Some(previous) = previous {
+                table.push([previous, vec![entry[0]]].concat());
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    #[timeout(1000)]
+    fn test_lzw_round_trip() {
+        let repeats = vec![1; 10_000];
+        assert_eq!(unlzw(&lzw(&repeats, 2), 2), repeats);
+
+        // Enough different runs to fill the table and start over
+        let noisy: Vec<u8> = (0..40_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        assert_eq!(unlzw(&lzw(&noisy, 8), 8), noisy);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_index_colors() {
+        let (palette, indexes) = index_colors(&[9, 9, 9, 0, 0, 0, 9, 9, 9]);
+        assert_eq!(palette, [[9, 9, 9], [0, 0, 0]]);
+        assert_eq!(indexes, [0, 1, 0]);
+
+        // Too many colors fall back to the fixed palette
+        let rainbow: Vec<u8> = (0..300u32)
+            .flat_map(|i| [i as u8, (i >> 8) as u8, 0])
+            .collect();
+        let (palette, indexes) = index_colors(&rainbow);
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[0xFF], [255, 255, 255]);
+        assert_eq!(indexes[299], 0x20);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_format_from_path() {
+        assert_eq!(VideoFormat::from_path("run.GIF"), Some(VideoFormat::Gif));
+        assert_eq!(VideoFormat::from_path("run.png"), Some(VideoFormat::Apng));
+        assert_eq!(
+            VideoFormat::from_path("a.b/run.y4m"),
+            Some(VideoFormat::Y4m)
+        );
+        assert_eq!(VideoFormat::from_path("run"), None);
+    }
+
+    #[test]
+    #[timeout(1000)]
+    fn test_y4m_and_wav() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("rbgb-recording-test.y4m");
+        let path = path.to_string_lossy();
+        let mut recorder = Recorder::create(&path, VideoFormat::Y4m).unwrap();
+        let white = vec![0xFF; (crate::SCREEN_WIDTH * crate::SCREEN_HEIGHT * 3) as usize];
+        for _ in 0..60 {
+            recorder.add_frame(&white).unwrap();
+        }
+        assert_eq!(recorder.finish(), Ok(60));
+
+        let video = std::fs::read(&*path).unwrap();
+        let header = b"YUV4MPEG2 W160 H144 F4194304:70224 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(&video[..header.len()], header);
+        assert_eq!(video.len(), header.len() - 6 + 60 * (6 + white.len()));
+        // White in limited range
+        assert_eq!(video[header.len()], 235);
+
+        // A second of video is a little over a second of sound
+        let audio = std::fs::read(dir.join("rbgb-recording-test.wav")).unwrap();
+        let samples = 60 * 44_100 * 70224 / 4_194_304;
+        assert_eq!(audio.len(), 44 + samples * 4);
+        assert_eq!(audio[40..44], ((samples * 4) as u32).to_le_bytes());
+    }
+
+    #[test]
+    #[timeout(1000)]
+    fn test_empty_apng() {
+        let path = std::env::temp_dir().join("rbgb-recording-test-empty.png");
+        let path = path.to_string_lossy();
+        let recorder = Recorder::create(&path, VideoFormat::Apng).unwrap();
+        assert_eq!(recorder.finish(), Ok(0));
+
+        // Still a valid PNG, holding a single frame
+        let video = std::fs::read(&*path).unwrap();
+        let start = ACTL_OFFSET as usize + 8;
+        assert_eq!(video[start..start + 8], actl(1));
+        assert!(video.windows(4).any(|chunk| chunk == b"IDAT"));
+        assert!(video.ends_with(&[0xAE, 0x42, 0x60, 0x82]));
+    }
+}
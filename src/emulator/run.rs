@@ -0,0 +1,25 @@
+//! Results of driving the emulator for less or more than a frame
+
+/// Why a run returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested number of cycles ran
+    Cycles,
+    /// The predicate passed to [`Emulator::run_until`](crate::Emulator::run_until)
+    /// returned `true`
+    Predicate,
+}
+
+/// What happened during a run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunResult {
+    /// Cycles that passed, in single speed clocks of 4.19 MHz
+    ///
+    /// Runs only stop between instructions, so this can be a few cycles past
+    /// what was asked for.
+    pub cycles: u64,
+    /// Frames finished along the way
+    pub frames: u32,
+    /// Why the run stopped
+    pub reason: StopReason,
+}
@@ -0,0 +1,191 @@
+//! Serial port (link cable) registers
+use crate::emulator::printer::{Print, Printer};
+use crate::types::*;
+
+/// T-cycles per bit when running off the internal 8192Hz clock
+const CYCLES_PER_BIT: u32 = 512;
+
+/// SB and SC
+///
+/// Without a link partner the other end of the cable reads as all ones, so a
+/// transfer on the internal clock shifts in 0xFF. Transfers on the external
+/// clock wait for a partner that never arrives. A printer can be plugged in,
+/// which answers transfers the Game Boy clocks.
+pub struct Serial {
+    data: Byte,
+    control: Byte,
+    bits_remaining: u8,
+    counter: u32,
+    /// Shifted in from the other end over the transfer
+    incoming: Byte,
+    printer: Option<Printer>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            data: 0,
+            control: 0,
+            bits_remaining: 0,
+            counter: 0,
+            incoming: 0xFF,
+            printer: None,
+        }
+    }
+
+    /// Clears the registers, leaving a printer plugged in but starting it over
+    pub fn reset(&mut self) {
+        *self = Serial {
+            printer: self.printer.as_ref().map(|_| Printer::new()),
+            ..Serial::new()
+        };
+    }
+
+    /// Plugs a printer into the link port, or unplugs it
+    pub fn connect_printer(&mut self, connected: bool) {
+        if connected != self.printer.is_some() {
+            self.printer = connected.then(Printer::new);
+        }
+    }
+
+    pub fn printer_connected(&self) -> bool {
+        self.printer.is_some()
+    }
+
+    pub fn take_print(&mut self) -> Option<Print> {
+        self.printer.as_mut()?.take_print()
+    }
+
+    /// Advances an active transfer by the given number of T-cycles
+    ///
+    /// Returns true if a serial interrupt should be requested
+    pub fn tick(&mut self, cycles: i32) -> bool {
+        if let Some(printer) = &mut self.printer {
+            printer.tick(cycles as u32);
+        }
+        if !self.transferring() || self.control & 0x1 == 0 {
+            return false;
+        }
+
+        self.counter += cycles as u32;
+        while self.counter >= CYCLES_PER_BIT && self.bits_remaining > 0 {
+            self.counter -= CYCLES_PER_BIT;
+            self.bits_remaining -= 1;
+            self.data = (self.data << 1) | ((self.incoming >> self.bits_remaining) & 1);
+        }
+
+        if self.bits_remaining == 0 {
+            self.control &= !0x80;
+            return true;
+        }
+        false
+    }
+
+    pub fn read(&self, addr: Word) -> Byte {
+        match addr {
+            SERIAL_DATA => self.data,
+            SERIAL_CONTROL => self.control | 0x7E,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: Word, value: Byte) {
+        match addr {
+            SERIAL_DATA => self.data = value,
+            SERIAL_CONTROL => {
+                self.control = value & 0x81;
+                if self.transferring() {
+                    self.bits_remaining = 8;
+                    self.counter = 0;
+                    // The printer only ever runs off the Game Boy's clock
+                    self.incoming = match &mut self.printer {
+                        Some(printer) if self.control & 0x1 != 0 => printer.exchange(self.data),
+                        _ => 0xFF,
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets SB or SC without starting a transfer
+    pub fn write_forced(&mut self, addr: Word, value: Byte) {
+        match addr {
+            SERIAL_DATA => self.data = value,
+            SERIAL_CONTROL => self.control = value & 0x81,
+            _ => {}
+        }
+    }
+
+    fn transferring(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_internal_clock_transfer() {
+        let mut serial = Serial::new();
+        serial.write(SERIAL_DATA, 0x42);
+        serial.write(SERIAL_CONTROL, 0x81);
+        assert_eq!(serial.read(SERIAL_CONTROL), 0xFF);
+
+        assert!(!serial.tick(CYCLES_PER_BIT as i32 * 8 - 4));
+        assert!(serial.tick(4));
+        assert_eq!(serial.read(SERIAL_DATA), 0xFF);
+        assert_eq!(serial.read(SERIAL_CONTROL), 0x7F);
+
+        // Nothing more happens once the transfer is done
+        assert!(!serial.tick(CYCLES_PER_BIT as i32 * 8));
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_external_clock_waits() {
+        let mut serial = Serial::new();
+        serial.write(SERIAL_DATA, 0x42);
+        serial.write(SERIAL_CONTROL, 0x80);
+
+        assert!(!serial.tick(CYCLES_PER_BIT as i32 * 16));
+        assert_eq!(serial.read(SERIAL_DATA), 0x42);
+        assert_eq!(serial.read(SERIAL_CONTROL), 0xFE);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_printer_replies() {
+        fn send<const N: usize>(serial: &mut Serial, bytes: [Byte; N]) -> [Byte; N] {
+            bytes.map(|byte| {
+                serial.write(SERIAL_DATA, byte);
+                serial.write(SERIAL_CONTROL, 0x81);
+                assert!(serial.tick(CYCLES_PER_BIT as i32 * 8));
+                serial.read(SERIAL_DATA)
+            })
+        }
+        // A status packet: magic, command, no compression, no data, checksum
+        const STATUS: [Byte; 10] = [0x88, 0x33, 0x0F, 0, 0, 0, 0x0F, 0, 0, 0];
+
+        let mut serial = Serial::new();
+        serial.connect_printer(true);
+        let replies = send(&mut serial, STATUS);
+        assert_eq!(replies[..8], [0; 8]);
+        assert_eq!(replies[8..], [0x81, 0]);
+
+        // A packet cut off by a reset doesn't carry over to the next game
+        send(&mut serial, [0x88, 0x33, 0x04]);
+        serial.reset();
+        assert!(serial.printer_connected());
+        assert_eq!(send(&mut serial, STATUS)[8..], [0x81, 0]);
+    }
+}
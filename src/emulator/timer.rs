@@ -0,0 +1,311 @@
+//! Divider and timer registers driven by the 16-bit system counter
+use crate::types::*;
+
+/// Where a TIMA overflow is in its delayed reload
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Reload {
+    Idle,
+    /// TIMA overflowed this M-cycle and currently reads 0x00
+    Pending,
+    /// TMA was copied into TIMA this M-cycle and the interrupt was requested
+    Reloading,
+}
+
+/// DIV, TIMA, TMA and TAC
+///
+/// DIV is the upper byte of a free running 16-bit counter that advances every
+/// T-cycle. TIMA is not clocked directly, instead it increments on the falling
+/// edge of one counter bit (selected by TAC) ANDed with the timer enable bit.
+/// Anything that makes that signal drop (DIV writes, TAC writes) also clocks TIMA.
+pub struct Timer {
+    counter: Word,
+    tima: Byte,
+    tma: Byte,
+    tac: Byte,
+    reload: Reload,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload: Reload::Idle,
+        }
+    }
+
+    /// Advances the timer by the given number of T-cycles
+    ///
+    /// Returns true if a timer interrupt should be requested
+    pub fn tick(&mut self, cycles: i32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles / 4 {
+            interrupt |= self.step();
+        }
+        interrupt
+    }
+
+    /// Reads one of the timer registers
+    pub fn read(&self, addr: Word) -> Byte {
+        match addr {
+            DIVIDER_REGISTER => (self.counter >> 8) as Byte,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TMC => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes one of the timer registers with all of the hardware side effects
+    pub fn write(&mut self, addr: Word, value: Byte) {
+        match addr {
+            DIVIDER_REGISTER => {
+                // Any write clears the whole counter which can drop the selected bit
+                let before = self.input();
+                self.counter = 0;
+                if before {
+                    self.increment_tima();
+                }
+            }
+            TIMA => match self.reload {
+                // Writing during the overflow cycle cancels the reload and interrupt
+                Reload::Pending => {
+                    self.tima = value;
+                    self.reload = Reload::Idle;
+                }
+                // TMA wins when written on the same cycle as the reload
+                Reload::Reloading => {}
+                Reload::Idle => self.tima = value,
+            },
+            TMA => {
+                self.tma = value;
+                if self.reload == Reload::Reloading {
+                    self.tima = value;
+                }
+            }
+            TMC => {
+                let before = self.input();
+                self.tac = value & 0x7;
+                if before && !self.input() {
+                    self.increment_tima();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets a timer register without any side effects
+    ///
+    /// Writing DIV sets the upper byte of the system counter
+    pub fn write_forced(&mut self, addr: Word, value: Byte) {
+        match addr {
+            DIVIDER_REGISTER => self.counter = (value as Word) << 8,
+            TIMA => self.tima = value,
+            TMA => self.tma = value,
+            TMC => self.tac = value & 0x7,
+            _ => {}
+        }
+    }
+
+    /// Sets the whole system counter, used for the DIV phase left by the boot ROM
+    pub fn set_counter(&mut self, counter: Word) {
+        self.counter = counter;
+    }
+
+    /// Counter bit watched by TIMA for the current TAC frequency
+    fn timer_bit(&self) -> u32 {
+        // Magic numbers come from CLOCKSPEED / frequency / 2
+        match self.tac & 0x3 {
+            0 => 9, // freq = 4096
+            1 => 3, // freq = 262144
+            2 => 5, // freq = 65536
+            _ => 7, // freq = 16384
+        }
+    }
+
+    /// The signal whose falling edge increments TIMA
+    fn input(&self) -> bool {
+        self.tac & 0x4 != 0 && self.counter & (1 << self.timer_bit()) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (value, overflow) = self.tima.overflowing_add(1);
+        self.tima = value;
+        if overflow {
+            self.reload = Reload::Pending;
+        }
+    }
+
+    /// Runs a single M-cycle
+    fn step(&mut self) -> bool {
+        let mut interrupt = false;
+        match self.reload {
+            Reload::Reloading => self.reload = Reload::Idle,
+            Reload::Pending => {
+                self.tima = self.tma;
+                self.reload = Reload::Reloading;
+                interrupt = true;
+            }
+            Reload::Idle => {}
+        }
+
+        let before = self.input();
+        self.counter = self.counter.wrapping_add(4);
+        if before && !self.input() {
+            self.increment_tima();
+        }
+
+        interrupt
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_div_follows_system_counter() {
+        let mut timer = Timer::new();
+
+        timer.tick(252);
+        assert_eq!(timer.read(DIVIDER_REGISTER), 0);
+        timer.tick(4);
+        assert_eq!(timer.read(DIVIDER_REGISTER), 1);
+
+        // Remainders are kept rather than dropped
+        timer.tick(256 * 3 + 128);
+        assert_eq!(timer.read(DIVIDER_REGISTER), 4);
+        assert_eq!(timer.counter, 0x480);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_div_write_resets_counter() {
+        let mut timer = Timer::new();
+        timer.tick(1000);
+
+        timer.write(DIVIDER_REGISTER, 0x55);
+        assert_eq!(timer.read(DIVIDER_REGISTER), 0);
+        assert_eq!(timer.counter, 0);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_tima_frequencies() {
+        let tests = [(0x4, 1024), (0x5, 16), (0x6, 64), (0x7, 256)];
+
+        for (tac, period) in tests {
+            let mut timer = Timer::new();
+            timer.write(TMC, tac);
+
+            timer.tick(period - 4);
+            assert_eq!(timer.read(TIMA), 0);
+            timer.tick(4);
+            assert_eq!(timer.read(TIMA), 1);
+        }
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_tima_disabled() {
+        let mut timer = Timer::new();
+        timer.write(TMC, 0x1);
+        timer.tick(1024);
+        assert_eq!(timer.read(TIMA), 0);
+        assert_eq!(timer.read(TMC), 0xF9);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_div_write_falling_edge_increments_tima() {
+        let mut timer = Timer::new();
+        timer.write(TMC, 0x5); // bit 3
+
+        timer.tick(8);
+        assert_eq!(timer.read(TIMA), 0);
+        timer.write(DIVIDER_REGISTER, 0);
+        assert_eq!(timer.read(TIMA), 1);
+
+        // The phase restarts from zero after the write
+        timer.tick(12);
+        assert_eq!(timer.read(TIMA), 1);
+        timer.tick(4);
+        assert_eq!(timer.read(TIMA), 2);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_tac_change_falling_edge_increments_tima() {
+        let mut timer = Timer::new();
+        timer.write(TMC, 0x5); // bit 3
+        timer.tick(8);
+
+        // Bit 9 is low so the selected signal drops
+        timer.write(TMC, 0x4);
+        assert_eq!(timer.read(TIMA), 1);
+
+        // Disabling the timer while the bit is high also clocks it
+        timer.write(TMC, 0x5);
+        timer.write(TMC, 0x1);
+        assert_eq!(timer.read(TIMA), 2);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_overflow_reload_is_delayed() {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0x42);
+        timer.write(TIMA, 0xFF);
+        timer.write(TMC, 0x5);
+
+        assert!(!timer.tick(16));
+        assert_eq!(timer.read(TIMA), 0x00);
+
+        assert!(timer.tick(4));
+        assert_eq!(timer.read(TIMA), 0x42);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_tima_write_during_overflow_cancels_reload() {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0x42);
+        timer.write(TIMA, 0xFF);
+        timer.write(TMC, 0x5);
+        timer.tick(16);
+
+        timer.write(TIMA, 0x10);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(TIMA), 0x10);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_writes_during_reload_cycle() {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0x42);
+        timer.write(TIMA, 0xFF);
+        timer.write(TMC, 0x5);
+        timer.tick(20);
+
+        // TIMA writes are ignored while TMA writes pass straight through
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0x42);
+        timer.write(TMA, 0x24);
+        assert_eq!(timer.read(TIMA), 0x24);
+
+        timer.tick(4);
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0x10);
+    }
+}
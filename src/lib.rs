@@ -7,11 +7,12 @@
 //! CPU, memory, graphics, and input devices and drives them per frame.
 //!
 //! High-level flow:
-//! - Create an [`Emulator`] (starts paused).
+//! - Create an [`Emulator`] (starts paused), or configure the model, palette
+//!   and boot ROM first with an [`EmulatorBuilder`].
 //! - Load a ROM with [`Emulator::load_rom`], which resets memory and CPU state.
 //! - Call [`Emulator::update`] once per frame to advance CPU, timers, and video.
 //! - Provide input through [`Emulator::game_input`], and read pixels from
-//!   [`Emulator::get_display_buffer`].
+//!   [`Emulator::get_display_buffer`] in the chosen [`PixelFormat`].
 //!
 //! The emulation state is shared across CPU, graphics, and joypad through a
 //! single memory model owned by the emulator core.
@@ -31,8 +32,12 @@
 //! }
 //! ```
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 /// The main emulator core
 pub mod emulator;
 mod types;
 
 pub use emulator::*;
+pub use types::{GameInput, KeyState, SCREEN_HEIGHT, SCREEN_WIDTH};
@@ -1,21 +1,141 @@
 #[cfg(feature = "gui")]
 extern crate sdl2;
 
-mod emulator;
+mod cli;
 mod sdl;
-mod types;
 
-use emulator::Emulator;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+};
+
+use cli::Options;
+use rbgb::{Emulator, EmulatorBuilder, Paper, Recorder, timestamped_name};
 use sdl::SdlApp;
 
 ///Main entry point to gameboy simulation
 fn main() -> Result<(), String> {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::HELP);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        print!("{}", cli::HELP);
+        return Ok(());
+    }
+
     println!("Starting emulator");
 
-    let mut emulator = Emulator::new();
-    let mut sdl_app = SdlApp::new()?;
+    let mut builder = EmulatorBuilder::new().model(options.model);
+    if let Some(palette) = options.palette {
+        builder = builder.palette(palette);
+    }
+    let mut emulator = builder.build().map_err(|e| e.to_string())?;
+    if let Some(path) = &options.boot_rom {
+        emulator.load_boot_rom(&path.to_string_lossy())?;
+    }
+    emulator.connect_printer(options.printer);
+
+    if options.headless {
+        return run_headless(&mut emulator, &options);
+    }
+
+    let mut sdl_app = SdlApp::new(&options)?;
+    if let Some(path) = &options.rom
+        && sdl_app.load_rom(&mut emulator, path)
+        && options.start_paused
+    {
+        emulator.toggle_pause();
+    }
+    if let Some((path, format)) = &options.record {
+        sdl_app.start_video_recording(path, *format);
+    }
     sdl_app.run(&mut emulator)
 }
 
+/// Runs the emulator as fast as it goes without opening a window
+fn run_headless(emulator: &mut Emulator, options: &Options) -> Result<(), String> {
+    let Some(rom) = &options.rom else {
+        return Err("--headless needs a ROM".to_string());
+    };
+    emulator.load_rom(&rom.to_string_lossy())?;
+    let mut recorder = match &options.record {
+        Some((path, format)) => Some(Recorder::create(&path.to_string_lossy(), *format)?),
+        None => None,
+    };
+
+    // With only a screenshot to take there's no point going past it
+    let limit = options.frames.or(options.screenshot_frame);
+    let mut frames = 0;
+    let mut paper = Paper::new();
+    let mut pages = 0;
+    while limit.is_none_or(|limit| frames < limit) {
+        if emulator.update() {
+            frames += 1;
+            if let Some(recorder) = &mut recorder {
+                emulator.record_frame(recorder)?;
+            }
+            if options.screenshot_frame == Some(frames) {
+                save_frame(emulator, options, rom, frames)?;
+            }
+            if let Some(print) = emulator.take_print()
+                && paper.add(&print)
+            {
+                save_page(&mut paper, &mut pages, options, rom)?;
+            }
+        }
+    }
+    println!("Ran {frames} frames");
+    // Whatever is still in the printer when the run ends
+    save_page(&mut paper, &mut pages, options, rom)?;
+    if let (Some(recorder), Some((path, _))) = (recorder, &options.record) {
+        recorder.finish()?;
+        println!("Saved video to {}", path.display());
+    }
+    Ok(())
+}
+
+// Named after the ROM, what it holds and the time, in the save dir or next to the ROM
+fn output_path(options: &Options, rom: &Path, label: &str) -> PathBuf {
+    let stem = rom.file_stem().unwrap_or_default().to_string_lossy();
+    let name = timestamped_name(&format!("{stem}-{label}"), "png");
+    match options.save_dir.as_deref().or_else(|| rom.parent()) {
+        Some(dir) => dir.join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+fn save_frame(
+    emulator: &Emulator,
+    options: &Options,
+    rom: &Path,
+    frame: u64,
+) -> Result<(), String> {
+    let path = output_path(options, rom, &format!("frame{frame}"));
+    emulator.save_screenshot(&path.to_string_lossy())?;
+    println!("Saved frame {frame} to {}", path.display());
+    Ok(())
+}
+
+fn save_page(
+    paper: &mut Paper,
+    pages: &mut u32,
+    options: &Options,
+    rom: &Path,
+) -> Result<(), String> {
+    if !paper.is_empty() {
+        *pages += 1;
+        let path = output_path(options, rom, &format!("print{pages}"));
+        std::fs::write(&path, paper.to_png()).map_err(|e| e.to_string())?;
+        println!("Saved print to {}", path.display());
+    }
+    paper.clear();
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {}
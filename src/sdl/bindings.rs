@@ -0,0 +1,325 @@
+//! Keyboard and controller bindings, kept in an INI style file in the user's config dir
+
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use rbgb::GameInput;
+use sdl2::{controller::Button, keyboard::Keycode};
+
+use super::io::{Action, Binding};
+use super::paths::config_dir;
+
+// How far the stick has to be pushed before it counts, out of 32767
+const DEFAULT_DEAD_ZONE: i16 = 10_000;
+
+/// Everything that can be bound, with the name used for it in the file
+pub const BINDINGS: [(&str, Binding); 25] = [
+    ("up", Binding::Game(GameInput::Up)),
+    ("down", Binding::Game(GameInput::Down)),
+    ("left", Binding::Game(GameInput::Left)),
+    ("right", Binding::Game(GameInput::Right)),
+    ("a", Binding::Game(GameInput::A)),
+    ("b", Binding::Game(GameInput::B)),
+    ("start", Binding::Game(GameInput::Start)),
+    ("select", Binding::Game(GameInput::Select)),
+    ("pause", Binding::Action(Action::Pause)),
+    ("load_rom", Binding::Action(Action::LoadRom)),
+    ("next_palette", Binding::Action(Action::NextPalette)),
+    ("dump_lcd", Binding::Action(Action::DumpLcd)),
+    ("record", Binding::Action(Action::Record)),
+    ("play_movie", Binding::Action(Action::PlayMovie)),
+    ("fast_forward", Binding::Action(Action::FastForward)),
+    ("bind_keys", Binding::Action(Action::BindKeys)),
+    ("fullscreen", Binding::Action(Action::Fullscreen)),
+    ("scale_mode", Binding::Action(Action::ScaleMode)),
+    ("next_filter", Binding::Action(Action::NextFilter)),
+    ("frame_blend", Binding::Action(Action::FrameBlend)),
+    ("show_fps", Binding::Action(Action::ShowFps)),
+    ("screenshot", Binding::Action(Action::Screenshot)),
+    (
+        "screenshot_filtered",
+        Binding::Action(Action::ScreenshotFiltered),
+    ),
+    ("record_video", Binding::Action(Action::RecordVideo)),
+    ("printer", Binding::Action(Action::Printer)),
+];
+
+pub struct Bindings {
+    keys: Vec<(Keycode, Binding)>,
+    buttons: Vec<(Button, Binding)>,
+    pub dead_zone: i16,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let game = |input| Binding::Game(input);
+        let action = |action| Binding::Action(action);
+        Self {
+            keys: vec![
+                (Keycode::W, game(GameInput::Up)),
+                (Keycode::S, game(GameInput::Down)),
+                (Keycode::A, game(GameInput::Left)),
+                (Keycode::D, game(GameInput::Right)),
+                (Keycode::Z, game(GameInput::A)),
+                (Keycode::X, game(GameInput::B)),
+                (Keycode::E, game(GameInput::Start)),
+                (Keycode::Q, game(GameInput::Select)),
+                (Keycode::P, action(Action::Pause)),
+                (Keycode::L, action(Action::LoadRom)),
+                (Keycode::C, action(Action::NextPalette)),
+                (Keycode::O, action(Action::DumpLcd)),
+                (Keycode::R, action(Action::Record)),
+                (Keycode::M, action(Action::PlayMovie)),
+                (Keycode::Tab, action(Action::FastForward)),
+                (Keycode::F1, action(Action::BindKeys)),
+                (Keycode::F11, action(Action::Fullscreen)),
+                (Keycode::F2, action(Action::ScaleMode)),
+                (Keycode::F3, action(Action::NextFilter)),
+                (Keycode::F4, action(Action::FrameBlend)),
+                (Keycode::F5, action(Action::ShowFps)),
+                (Keycode::F12, action(Action::Screenshot)),
+                (Keycode::F10, action(Action::ScreenshotFiltered)),
+                (Keycode::F9, action(Action::RecordVideo)),
+                (Keycode::F6, action(Action::Printer)),
+            ],
+            // Buttons in the same places as on a Game Boy, with A on the right
+            buttons: vec![
+                (Button::DPadUp, game(GameInput::Up)),
+                (Button::DPadDown, game(GameInput::Down)),
+                (Button::DPadLeft, game(GameInput::Left)),
+                (Button::DPadRight, game(GameInput::Right)),
+                (Button::B, game(GameInput::A)),
+                (Button::A, game(GameInput::B)),
+                (Button::Start, game(GameInput::Start)),
+                (Button::Back, game(GameInput::Select)),
+                (Button::Guide, action(Action::Pause)),
+                (Button::LeftShoulder, action(Action::Pause)),
+                (Button::RightShoulder, action(Action::FastForward)),
+            ],
+            dead_zone: DEFAULT_DEAD_ZONE,
+        }
+    }
+}
+
+impl Bindings {
+    /// Reads the bindings file, writing out the defaults if there isn't one yet
+    pub fn load() -> Self {
+        let Some(path) = config_path() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => match Self::parse(&text) {
+                Ok(bindings) => bindings,
+                Err(e) => {
+                    println!("Ignoring {}: {e}", path.display());
+                    Self::default()
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let bindings = Self::default();
+                bindings.save();
+                bindings
+            }
+            Err(e) => {
+                println!("Failed to read {}: {e}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = config_path() else {
+            println!("No config directory to save key bindings in");
+            return;
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, self.to_ini()));
+        match result {
+            Ok(()) => println!("Saved key bindings to {}", path.display()),
+            Err(e) => println!("Failed to save key bindings: {e}"),
+        }
+    }
+
+    /// What a key does, if anything
+    pub fn key(&self, key: Keycode) -> Option<Binding> {
+        self.keys.iter().find(|(k, _)| *k == key).map(|(_, b)| *b)
+    }
+
+    /// What a controller button does, if anything
+    pub fn button(&self, button: Button) -> Option<Binding> {
+        self.buttons
+            .iter()
+            .find(|(b, _)| *b == button)
+            .map(|(_, b)| *b)
+    }
+
+    /// Replaces the keys bound to something with a single key, which is taken
+    /// away from whatever it did before
+    pub fn bind_key(&mut self, key: Keycode, binding: Binding) {
+        self.keys.retain(|(k, b)| *k != key && *b != binding);
+        self.keys.push((key, binding));
+    }
+
+    /// Names of the keys bound to something, separated by commas
+    pub fn key_names(&self, binding: Binding) -> String {
+        let names: Vec<String> = self
+            .keys
+            .iter()
+            .filter(|(_, b)| *b == binding)
+            .map(|(k, _)| k.name())
+            .collect();
+        names.join(", ")
+    }
+
+    /// Parses a bindings file
+    ///
+    /// The `[keyboard]` and `[controller]` sections hold lines like `a = Z, K`
+    /// listing every key or button for a binding, using SDL's names for them.
+    /// Anything left out of a section keeps its default, and `dead_zone` in the
+    /// controller section sets how far the stick has to move.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut bindings = Self::default();
+        let mut section = "";
+        // Sections replace the defaults for anything they mention
+        let mut cleared: Vec<(&str, Binding)> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |e: &str| format!("line {}: {e}", number + 1);
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name.trim() {
+                    name @ ("keyboard" | "controller") => name,
+                    _ => return Err(error("unknown section")),
+                };
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected name = value"))?;
+            let (name, value) = (name.trim(), value.trim());
+            if section == "controller" && name == "dead_zone" {
+                let dead_zone: i16 = value.parse().map_err(|_| error("bad dead zone"))?;
+                bindings.dead_zone = dead_zone.clamp(0, i16::MAX - 1);
+                continue;
+            }
+            let (_, binding) = BINDINGS
+                .iter()
+                .find(|(n, _)| *n == name)
+                .ok_or_else(|| error("unknown binding"))?;
+
+            if !cleared.contains(&(section, *binding)) {
+                cleared.push((section, *binding));
+                bindings
+                    .keys
+                    .retain(|(_, b)| section != "keyboard" || b != binding);
+                bindings
+                    .buttons
+                    .retain(|(_, b)| section != "controller" || b != binding);
+            }
+            for value in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+                match section {
+                    "keyboard" => {
+                        let key = Keycode::from_name(value).ok_or_else(|| error("unknown key"))?;
+                        bindings.keys.push((key, *binding));
+                    }
+                    "controller" => {
+                        let button =
+                            Button::from_string(value).ok_or_else(|| error("unknown button"))?;
+                        bindings.buttons.push((button, *binding));
+                    }
+                    _ => return Err(error("binding outside of a section")),
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    pub fn to_ini(&self) -> String {
+        let mut ini = String::from("# Keys and buttons for each action, separated by commas\n");
+        ini.push_str("\n[keyboard]\n");
+        for (name, binding) in BINDINGS {
+            ini.push_str(&format!("{name} = {}\n", self.key_names(binding)));
+        }
+
+        ini.push_str("\n[controller]\n");
+        ini.push_str(&format!("dead_zone = {}\n", self.dead_zone));
+        for (name, binding) in BINDINGS {
+            let buttons: Vec<String> = self
+                .buttons
+                .iter()
+                .filter(|(_, b)| *b == binding)
+                .map(|(button, _)| button.string())
+                .collect();
+            ini.push_str(&format!("{name} = {}\n", buttons.join(", ")));
+        }
+        ini
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("bindings.ini"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_round_trip() {
+        let mut bindings = Bindings::default();
+        bindings.bind_key(Keycode::K, Binding::Game(GameInput::A));
+        bindings.dead_zone = 5000;
+
+        let ini = bindings.to_ini();
+        let parsed = Bindings::parse(&ini).unwrap();
+        assert_eq!(parsed.to_ini(), ini);
+        assert_eq!(parsed.key(Keycode::K), Some(Binding::Game(GameInput::A)));
+        assert_eq!(parsed.key(Keycode::Z), None);
+        assert_eq!(parsed.dead_zone, 5000);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_several_keys() {
+        let bindings = Bindings::parse("[keyboard]\na = Z, K\n\n[controller]\nb = X, Y").unwrap();
+        assert_eq!(bindings.key(Keycode::Z), Some(Binding::Game(GameInput::A)));
+        assert_eq!(bindings.key(Keycode::K), Some(Binding::Game(GameInput::A)));
+        assert_eq!(bindings.key_names(Binding::Game(GameInput::A)), "Z, K");
+        assert_eq!(
+            bindings.button(Button::X),
+            Some(Binding::Game(GameInput::B))
+        );
+        assert_eq!(
+            bindings.button(Button::Y),
+            Some(Binding::Game(GameInput::B))
+        );
+        assert_eq!(bindings.button(Button::A), None);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_section_override() {
+        let bindings = Bindings::parse("[keyboard]\na = K\nb =\n").unwrap();
+        // Only the keyboard bindings that are mentioned change
+        assert_eq!(bindings.key(Keycode::K), Some(Binding::Game(GameInput::A)));
+        assert_eq!(bindings.key(Keycode::Z), None);
+        assert_eq!(bindings.key(Keycode::X), None);
+        assert_eq!(bindings.key(Keycode::W), Some(Binding::Game(GameInput::Up)));
+        assert_eq!(
+            bindings.button(Button::B),
+            Some(Binding::Game(GameInput::A))
+        );
+        assert_eq!(bindings.dead_zone, DEFAULT_DEAD_ZONE);
+
+        assert!(Bindings::parse("[mouse]\n").is_err());
+        assert!(Bindings::parse("a = Z\n").is_err());
+        assert!(Bindings::parse("[keyboard]\na = NotAKey\n").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! ROM picker drawn into the window, with the recently played ROMs at the top
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use sdl2::{keyboard::Keycode, pixels::Color, rect::Rect, render::Canvas, video::Window};
+
+use super::font::{self, LINE_HEIGHT};
+use super::paths::config_dir;
+
+// How many ROMs the history remembers
+const MAX_RECENT: usize = 10;
+
+const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+const BACKGROUND: Color = Color::RGB(24, 24, 32);
+const HIGHLIGHT: Color = Color::RGB(60, 70, 120);
+const TEXT: Color = Color::RGB(230, 230, 230);
+const DIM: Color = Color::RGB(140, 140, 160);
+
+enum Entry {
+    Recent(PathBuf),
+    Parent,
+    Dir(PathBuf),
+    Rom(PathBuf),
+}
+
+/// What the browser wants done after a key press
+pub enum Outcome {
+    Open(PathBuf),
+    Close,
+}
+
+pub struct Browser {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+    selected: usize,
+    // First entry shown, so the selection stays on screen
+    scroll: usize,
+    // Entries that fit on screen, from the last draw
+    rows: usize,
+}
+
+impl Browser {
+    pub fn new(dir: PathBuf, recent: &[PathBuf]) -> Self {
+        let mut browser = Self {
+            dir: absolute(dir),
+            entries: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            rows: 1,
+        };
+        browser.read_dir(recent);
+        browser
+    }
+
+    // Lists the recent ROMs that still exist, then the folders and ROMs in the directory
+    fn read_dir(&mut self, recent: &[PathBuf]) {
+        self.entries = recent
+            .iter()
+            .filter(|path| path.is_file())
+            .map(|path| Entry::Recent(path.clone()))
+            .collect();
+        if self.dir.parent().is_some() {
+            self.entries.push(Entry::Parent);
+        }
+
+        let mut dirs = Vec::new();
+        let mut roms = Vec::new();
+        match fs::read_dir(&self.dir) {
+            Ok(listing) => {
+                for path in listing.flatten().map(|entry| entry.path()) {
+                    if path.is_dir() {
+                        dirs.push(path);
+                    } else if is_rom(&path) {
+                        roms.push(path);
+                    }
+                }
+            }
+            Err(e) => println!("Failed to read {}: {e}", self.dir.display()),
+        }
+        dirs.sort();
+        roms.sort();
+        self.entries.extend(dirs.into_iter().map(Entry::Dir));
+        self.entries.extend(roms.into_iter().map(Entry::Rom));
+
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    fn change_dir(&mut self, dir: PathBuf, recent: &[PathBuf]) {
+        self.dir = absolute(dir);
+        self.read_dir(recent);
+        // Start on the folder rather than the history once it has been left
+        self.selected = self
+            .entries
+            .iter()
+            .position(|entry| !matches!(entry, Entry::Recent(_)))
+            .unwrap_or_default();
+    }
+
+    pub fn handle_key(&mut self, key: Keycode, recent: &[PathBuf]) -> Option<Outcome> {
+        let last = self.entries.len().saturating_sub(1);
+        match key {
+            Keycode::Escape => return Some(Outcome::Close),
+            Keycode::Up => self.selected = self.selected.checked_sub(1).unwrap_or(last),
+            Keycode::Down => {
+                self.selected = if self.selected >= last {
+                    0
+                } else {
+                    self.selected + 1
+                }
+            }
+            Keycode::PageUp => self.selected = self.selected.saturating_sub(self.rows),
+            Keycode::PageDown => self.selected = (self.selected + self.rows).min(last),
+            Keycode::Home => self.selected = 0,
+            Keycode::End => self.selected = last,
+            Keycode::Backspace | Keycode::Left => {
+                if let Some(parent) = self.dir.parent() {
+                    self.change_dir(parent.to_path_buf(), recent);
+                }
+            }
+            Keycode::Return | Keycode::KpEnter | Keycode::Right => {
+                match self.entries.get(self.selected)? {
+                    Entry::Recent(path) | Entry::Rom(path) => {
+                        return Some(Outcome::Open(path.clone()));
+                    }
+                    Entry::Dir(path) => self.change_dir(path.clone(), recent),
+                    Entry::Parent => {
+                        if let Some(parent) = self.dir.parent() {
+                            self.change_dir(parent.to_path_buf(), recent);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Draws the browser over the whole window
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        let (width, height) = canvas.output_size()?;
+        let scale = (height / 240).max(1);
+        let line = LINE_HEIGHT * scale;
+        let columns = (width / (font::CHAR_WIDTH * scale)).saturating_sub(2) as usize;
+        let margin = (font::CHAR_WIDTH * scale) as i32;
+
+        canvas.set_draw_color(BACKGROUND);
+        canvas.clear();
+
+        let title = format!("Load ROM: {}", self.dir.display());
+        font::draw_text(canvas, margin, margin, scale, TEXT, &fit(&title, columns))?;
+        let hint = "Enter: open  Backspace: up  Esc: cancel";
+        let bottom = height as i32 - margin - line as i32;
+        font::draw_text(canvas, margin, bottom, scale, DIM, &fit(hint, columns))?;
+
+        // Keep the selection in the rows between the title and the hint
+        let top = margin + 2 * line as i32;
+        self.rows = ((bottom - top) as u32 / line).max(1) as usize;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+
+        if self.entries.is_empty() {
+            font::draw_text(canvas, margin, top, scale, DIM, "No ROMs here")?;
+        }
+        for (row, (index, entry)) in self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(self.rows)
+            .enumerate()
+        {
+            let y = top + (row as u32 * line) as i32;
+            if index == self.selected {
+                canvas.set_draw_color(HIGHLIGHT);
+                canvas.fill_rect(Rect::new(0, y - scale as i32, width, line))?;
+            }
+            let (label, color) = match entry {
+                Entry::Recent(path) => (format!("* {}", file_name(path)), TEXT),
+                Entry::Parent => ("../".to_string(), DIM),
+                Entry::Dir(path) => (format!("{}/", file_name(path)), DIM),
+                Entry::Rom(path) => (file_name(path), TEXT),
+            };
+            font::draw_text(canvas, margin, y, scale, color, &fit(&label, columns))?;
+        }
+        Ok(())
+    }
+}
+
+// Resolves a folder to a full path so going up always has somewhere to go,
+// using the working directory if the folder can't be found
+fn absolute(dir: PathBuf) -> PathBuf {
+    fs::canonicalize(&dir)
+        .or_else(|_| env::current_dir())
+        .unwrap_or(dir)
+}
+
+fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom| rom.eq_ignore_ascii_case(ext))
+        })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+// Cuts text down to a number of characters, keeping the end since that's the
+// interesting part of a path
+fn fit(text: &str, columns: usize) -> String {
+    let len = text.chars().count();
+    if len <= columns {
+        return text.to_string();
+    }
+    let skip = len - columns.saturating_sub(3);
+    format!("...{}", text.chars().skip(skip).collect::<String>())
+}
+
+fn recent_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("recent.txt"))
+}
+
+/// The recently played ROMs, newest first
+pub fn load_recent() -> Vec<PathBuf> {
+    recent_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|text| {
+            text.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(PathBuf::from)
+                .take(MAX_RECENT)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Moves a ROM to the top of the history and saves it
+pub fn add_recent(recent: &mut Vec<PathBuf>, rom: &Path) {
+    let rom = fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+    recent.retain(|path| *path != rom);
+    recent.insert(0, rom);
+    recent.truncate(MAX_RECENT);
+
+    let Some(path) = recent_path() else {
+        return;
+    };
+    let text: String = recent
+        .iter()
+        .map(|rom| format!("{}\n", rom.display()))
+        .collect();
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, text));
+    if let Err(e) = result {
+        println!("Failed to save recent ROMs: {e}");
+    }
+}
@@ -0,0 +1,169 @@
+//! Game controllers, with hot-plugging and the analog stick acting as a D-pad
+
+use rbgb::{Emulator, GameInput, KeyState};
+use sdl2::{
+    GameControllerSubsystem,
+    controller::{Axis, GameController},
+    event::Event,
+};
+
+use super::bindings::Bindings;
+use super::io::{Action, Binding};
+
+// Bits used to track held directions, in the order of GameInput's D-pad
+const DIRECTIONS: [GameInput; 4] = [
+    GameInput::Up,
+    GameInput::Down,
+    GameInput::Left,
+    GameInput::Right,
+];
+
+struct Pad {
+    controller: GameController,
+    // Directions the left stick is pushed in, a bit per entry in DIRECTIONS
+    stick: u8,
+}
+
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<Pad>,
+}
+
+impl Controllers {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Self {
+            subsystem,
+            pads: Vec::new(),
+        }
+    }
+
+    /// Handles controller events, passing Game Boy buttons to the emulator
+    ///
+    /// Returns a frontend action that was pressed or released, and whether it
+    /// was pressed
+    pub fn handle_event(
+        &mut self,
+        event: &Event,
+        bindings: &Bindings,
+        emulator: &mut Emulator,
+    ) -> Option<(Action, bool)> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                self.connect(which);
+                None
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.disconnect(which, emulator);
+                None
+            }
+            Event::ControllerButtonDown { button, .. } => {
+                Self::button(bindings.button(button)?, true, emulator)
+            }
+            Event::ControllerButtonUp { button, .. } => {
+                Self::button(bindings.button(button)?, false, emulator)
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                self.axis(which, axis, value, bindings.dead_zone, emulator);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn connect(&mut self, joystick_index: u32) {
+        match self.subsystem.open(joystick_index) {
+            Ok(controller) => {
+                println!("Controller connected: {}", controller.name());
+                self.pads.push(Pad {
+                    controller,
+                    stick: 0,
+                });
+            }
+            Err(e) => println!("Failed to open controller: {e}"),
+        }
+    }
+
+    fn disconnect(&mut self, instance_id: u32, emulator: &mut Emulator) {
+        let Some(index) = self
+            .pads
+            .iter()
+            .position(|pad| pad.controller.instance_id() == instance_id)
+        else {
+            return;
+        };
+        let pad = self.pads.remove(index);
+        println!("Controller disconnected: {}", pad.controller.name());
+        // Don't leave the game with a direction stuck down
+        self.set_stick(None, 0, emulator);
+    }
+
+    fn button(binding: Binding, pressed: bool, emulator: &mut Emulator) -> Option<(Action, bool)> {
+        match binding {
+            Binding::Game(input) => {
+                let state = if pressed {
+                    KeyState::Pressed
+                } else {
+                    KeyState::Released
+                };
+                emulator.game_input(input, state);
+                None
+            }
+            Binding::Action(action) => Some((action, pressed)),
+        }
+    }
+
+    fn axis(
+        &mut self,
+        instance_id: u32,
+        axis: Axis,
+        value: i16,
+        dead_zone: i16,
+        emulator: &mut Emulator,
+    ) {
+        let Some(index) = self
+            .pads
+            .iter()
+            .position(|pad| pad.controller.instance_id() == instance_id)
+        else {
+            return;
+        };
+
+        let (negative, positive) = match axis {
+            Axis::LeftY => (0, 1),
+            Axis::LeftX => (2, 3),
+            _ => return,
+        };
+        let mut stick = self.pads[index].stick & !((1 << negative) | (1 << positive));
+        if value < -dead_zone {
+            stick |= 1 << negative;
+        } else if value > dead_zone {
+            stick |= 1 << positive;
+        }
+        self.set_stick(Some(index), stick, emulator);
+    }
+
+    /// Updates one pad's stick and sends the directions that changed across
+    /// all pads to the emulator
+    fn set_stick(&mut self, index: Option<usize>, stick: u8, emulator: &mut Emulator) {
+        let held = |pads: &[Pad]| pads.iter().fold(0, |held, pad| held | pad.stick);
+        let before = held(&self.pads);
+        if let Some(index) = index {
+            self.pads[index].stick = stick;
+        }
+        let after = held(&self.pads);
+
+        for (bit, input) in DIRECTIONS.iter().enumerate() {
+            let mask = 1 << bit;
+            if before & mask != after & mask {
+                let state = if after & mask != 0 {
+                    KeyState::Pressed
+                } else {
+                    KeyState::Released
+                };
+                emulator.game_input(*input, state);
+            }
+        }
+    }
+}
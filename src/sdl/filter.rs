@@ -0,0 +1,316 @@
+//! Software upscaling and LCD effects applied to frames before they are shown
+//!
+//! Everything runs on the CPU over the RGB888 frame, so it looks the same on
+//! machines without GPU acceleration.
+
+use rbgb::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// How frames are upscaled before they go to the window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Plain pixels, stretched by SDL
+    #[default]
+    None,
+    /// EPX/Scale2x, rounds off the corners of diagonal edges
+    Scale2x,
+    /// AdvMAME3x/Scale3x
+    Scale3x,
+    /// Blends along edges the way xBR and hqx do, for smoother curves
+    Smooth2x,
+    /// Each pixel drawn as a dot with a dark gap around it, like the DMG's screen
+    LcdGrid,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::None,
+        Filter::Scale2x,
+        Filter::Scale3x,
+        Filter::Smooth2x,
+        Filter::LcdGrid,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|f| *f == self)
+            .unwrap_or_default();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::None => "none",
+            Filter::Scale2x => "Scale2x",
+            Filter::Scale3x => "Scale3x",
+            Filter::Smooth2x => "smooth 2x",
+            Filter::LcdGrid => "LCD grid",
+        }
+    }
+
+    /// How many times larger the output is than the Game Boy screen
+    pub fn scale(self) -> u32 {
+        match self {
+            Filter::None => 1,
+            Filter::Scale2x | Filter::Smooth2x => 2,
+            Filter::Scale3x | Filter::LcdGrid => 3,
+        }
+    }
+}
+
+/// Applies the chosen filter, keeping the buffers between frames
+#[derive(Default)]
+pub struct Filters {
+    pub filter: Filter,
+    /// Averages each frame with the one before, like the slow DMG LCD does.
+    /// Games that flicker sprites on alternate frames rely on it for
+    /// transparency.
+    pub blend: bool,
+    previous: Vec<u32>,
+    pixels: Vec<u32>,
+    scaled: Vec<u32>,
+    output: Vec<u8>,
+    // Size of the last output
+    width: u32,
+    height: u32,
+}
+
+impl Filters {
+    /// Filters an RGB888 frame, returning the RGB888 result and its width and height
+    pub fn apply(&mut self, frame: &[u8]) -> (&[u8], u32, u32) {
+        self.pixels.clear();
+        self.pixels.extend(
+            frame
+                .chunks_exact(3)
+                .take((SCREEN_WIDTH * SCREEN_HEIGHT) as usize)
+                .map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]])),
+        );
+
+        if self.blend {
+            if self.previous.len() != self.pixels.len() {
+                self.previous.clone_from(&self.pixels);
+            }
+            for (pixel, previous) in self.pixels.iter_mut().zip(self.previous.iter_mut()) {
+                let current = *pixel;
+                *pixel = mix(current, *previous, 1, 1);
+                *previous = current;
+            }
+        } else {
+            self.previous.clear();
+        }
+
+        let scale = self.filter.scale();
+        let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+        self.scaled.clear();
+        self.scaled.resize((width * height) as usize, 0);
+        let image = Image {
+            pixels: &self.pixels,
+            width: SCREEN_WIDTH as usize,
+            height: SCREEN_HEIGHT as usize,
+        };
+        match self.filter {
+            Filter::None => self.scaled.copy_from_slice(&self.pixels),
+            Filter::Scale2x => scale2x(&image, &mut self.scaled),
+            Filter::Scale3x => scale3x(&image, &mut self.scaled),
+            Filter::Smooth2x => smooth2x(&image, &mut self.scaled),
+            Filter::LcdGrid => lcd_grid(&image, &mut self.scaled),
+        }
+
+        self.output.clear();
+        self.output.extend(self.scaled.iter().flat_map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b]
+        }));
+        (self.width, self.height) = (width, height);
+        (&self.output, width, height)
+    }
+
+    /// The last filtered frame, as returned by `apply`
+    pub fn last(&self) -> (&[u8], u32, u32) {
+        (&self.output, self.width, self.height)
+    }
+}
+
+struct Image<'a> {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize,
+}
+
+impl Image<'_> {
+    // Pixels off the edge repeat the nearest one inside
+    fn get(&self, x: isize, y: isize) -> u32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// The 3x3 block around a pixel, row by row
+    fn neighbours(&self, x: usize, y: usize) -> [u32; 9] {
+        let (x, y) = (x as isize, y as isize);
+        let mut block = [0; 9];
+        for (i, pixel) in block.iter_mut().enumerate() {
+            let (dx, dy) = (i as isize % 3 - 1, i as isize / 3 - 1);
+            *pixel = self.get(x + dx, y + dy);
+        }
+        block
+    }
+
+    // Fills `out` with the block `write` makes for each pixel from its neighbours
+    fn scale_each(&self, scale: usize, out: &mut [u32], write: impl Fn([u32; 9]) -> [u32; 9]) {
+        let out_width = self.width * scale;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block = write(self.neighbours(x, y));
+                for dy in 0..scale {
+                    let row = (y * scale + dy) * out_width + x * scale;
+                    out[row..row + scale].copy_from_slice(&block[dy * scale..dy * scale + scale]);
+                }
+            }
+        }
+    }
+}
+
+// Neighbours are named as in the Scale2x docs:
+//   A B C
+//   D E F
+//   G H I
+fn scale2x(image: &Image, out: &mut [u32]) {
+    image.scale_each(2, out, |[_, b, _, d, e, f, _, h, _]| {
+        let mut block = [e; 9];
+        if b != h && d != f {
+            block[0] = if d == b { d } else { e };
+            block[1] = if b == f { f } else { e };
+            block[2] = if d == h { d } else { e };
+            block[3] = if h == f { f } else { e };
+        }
+        block
+    });
+}
+
+fn scale3x(image: &Image, out: &mut [u32]) {
+    image.scale_each(3, out, |[a, b, c, d, e, f, g, h, i]| {
+        if b == h || d == f {
+            return [e; 9];
+        }
+        let pick = |cond: bool, other: u32| if cond { other } else { e };
+        [
+            pick(d == b, d),
+            pick((d == b && e != c) || (b == f && e != a), b),
+            pick(b == f, f),
+            pick((d == b && e != g) || (d == h && e != a), d),
+            e,
+            pick((b == f && e != i) || (h == f && e != c), f),
+            pick(d == h, d),
+            pick((d == h && e != i) || (h == f && e != g), h),
+            pick(h == f, f),
+        ]
+    });
+}
+
+// Scale2x's rules with colors compared by how alike they look rather than
+// exactly, blending into the corner instead of replacing it so edges come out
+// anti-aliased
+fn smooth2x(image: &Image, out: &mut [u32]) {
+    image.scale_each(2, out, |[a, b, c, d, e, f, g, h, i]| {
+        let corner = |side1: u32, side2: u32, across1: u32, across2: u32, diagonal: u32| {
+            if !similar(side1, side2) || similar(side1, across1) || similar(side2, across2) {
+                return e;
+            }
+            if similar(e, side1) {
+                return e;
+            }
+            let edge = mix(side1, side2, 1, 1);
+            // A lone corner pixel on a straight edge only gets softened a little
+            if similar(e, diagonal) {
+                mix(edge, e, 1, 3)
+            } else {
+                mix(edge, e, 1, 1)
+            }
+        };
+        let mut block = [e; 9];
+        block[0] = corner(d, b, f, h, a);
+        block[1] = corner(b, f, h, d, c);
+        block[2] = corner(h, d, b, f, g);
+        block[3] = corner(f, h, d, b, i);
+        block
+    });
+}
+
+fn lcd_grid(image: &Image, out: &mut [u32]) {
+    image.scale_each(3, out, |[_, _, _, _, e, _, _, _, _]| {
+        let gap = mix(e, 0, 3, 1);
+        [e, e, gap, e, e, gap, gap, gap, gap]
+    });
+}
+
+// Weighted average of two colors, channel by channel
+fn mix(x: u32, y: u32, x_weight: u32, y_weight: u32) -> u32 {
+    let total = x_weight + y_weight;
+    let channel = |shift: u32| {
+        let x = (x >> shift) & 0xFF;
+        let y = (y >> shift) & 0xFF;
+        ((x * x_weight + y * y_weight) / total) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
+
+// Whether two colors look alike, using hqx's thresholds in YUV
+fn similar(x: u32, y: u32) -> bool {
+    let yuv = |pixel: u32| {
+        let r = ((pixel >> 16) & 0xFF) as i32;
+        let g = ((pixel >> 8) & 0xFF) as i32;
+        let b = (pixel & 0xFF) as i32;
+        (
+            (299 * r + 587 * g + 114 * b) / 1000,
+            (-169 * r - 331 * g + 500 * b) / 1000,
+            (500 * r - 419 * g - 81 * b) / 1000,
+        )
+    };
+    let (y1, u1, v1) = yuv(x);
+    let (y2, u2, v2) = yuv(y);
+    (y1 - y2).abs() <= 48 && (u1 - u2).abs() <= 7 && (v1 - v2).abs() <= 6
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_scale2x_rounds_corners() {
+        // A white pixel with black above and to the left
+        let pixels = [0, 0, 0, 0xFFFFFF];
+        let image = Image {
+            pixels: &pixels,
+            width: 2,
+            height: 2,
+        };
+        let mut out = [0; 16];
+        scale2x(&image, &mut out);
+        // The top left of the white pixel takes the black edge
+        assert_eq!(out[2 * 4 + 2], 0);
+        assert_eq!(out[3 * 4 + 3], 0xFFFFFF);
+    }
+
+    #[test]
+    #[timeout(10)]
+    fn test_frame_blending() {
+        let mut filters = Filters {
+            blend: true,
+            ..Filters::default()
+        };
+        let black = vec![0; (SCREEN_WIDTH * SCREEN_HEIGHT * 3) as usize];
+        let white = vec![0xFF; black.len()];
+        filters.apply(&black);
+        let (output, width, height) = filters.apply(&white);
+        assert_eq!((width, height), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        assert_eq!(output[0], 0x7F);
+
+        filters.filter = Filter::LcdGrid;
+        let (output, width, _) = filters.apply(&white);
+        assert_eq!(width, SCREEN_WIDTH * 3);
+        assert_eq!(output.len(), black.len() * 9);
+    }
+}
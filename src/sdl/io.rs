@@ -1,53 +1,67 @@
 //! Helper file for annoying and long IO functions
 
-use crate::{
-    Emulator,
-    types::{GameInput, KeyState},
-};
+use rbgb::{Emulator, GameInput, KeyState};
 use sdl2::{event::Event, keyboard::Keycode};
 
-pub fn handle_joystick_input(event: Event, emulator: &mut Emulator) {
+use super::bindings::Bindings;
+
+/// Things the frontend does besides pressing Game Boy buttons
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Pause,
+    LoadRom,
+    NextPalette,
+    DumpLcd,
+    Record,
+    PlayMovie,
+    // Runs faster than real time while held
+    FastForward,
+    // Asks for a new key for everything in turn
+    BindKeys,
+    Fullscreen,
+    // Switches between integer and fit to window scaling
+    ScaleMode,
+    NextFilter,
+    // Mixes each frame with the last like the DMG's slow LCD
+    FrameBlend,
+    // Frame rate and emulation speed in the corner
+    ShowFps,
+    Screenshot,
+    // Screenshot as shown in the window, with the filter and scale
+    ScreenshotFiltered,
+    // Video of the screen, where Record makes a movie of the inputs
+    RecordVideo,
+    // Plugs the Game Boy Printer in or out
+    Printer,
+}
+
+/// What a key or button does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Game(GameInput),
+    Action(Action),
+}
+
+pub fn handle_joystick_input(event: Event, bindings: &Bindings, emulator: &mut Emulator) {
     match event {
         // Register key down inputs
         Event::KeyDown {
             keycode: Some(key), ..
-        } => emulator.game_input(register_key(key), KeyState::Pressed),
+        } => emulator.game_input(register_key(bindings, key), KeyState::Pressed),
 
         // Register key up inputs
         Event::KeyUp {
             keycode: Some(key), ..
-        } => emulator.game_input(register_key(key), KeyState::Released),
+        } => emulator.game_input(register_key(bindings, key), KeyState::Released),
 
         // otherwise do nothing
         _ => (),
     }
 }
 
-fn register_key(key: Keycode) -> GameInput {
-    match key {
-        // Go up
-        Keycode::W => GameInput::Up,
-
-        // Go down
-        Keycode::S => GameInput::Down,
-
-        // Go left
-        Keycode::A => GameInput::Left,
-
-        // Go right
-        Keycode::D => GameInput::Right,
-
-        // Go stop
-        Keycode::Q => GameInput::Select,
-
-        // Go start
-        Keycode::E => GameInput::Start,
-
-        // Go A
-        Keycode::Z => GameInput::A,
-        // Go B
-        Keycode::X => GameInput::B,
-
+fn register_key(bindings: &Bindings, key: Keycode) -> GameInput {
+    match bindings.key(key) {
+        Some(Binding::Game(input)) => input,
         // gets ignored
         _ => GameInput::Unknown,
     }
@@ -1,4 +1,12 @@
+mod bindings;
+mod browser;
+mod controller;
+mod filter;
+mod font;
 mod io;
+mod osd;
+mod paths;
 pub mod screen;
+mod window;
 
 pub use screen::*;
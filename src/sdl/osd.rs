@@ -0,0 +1,197 @@
+//! On-screen display: status messages, the FPS counter and the pause menu
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use sdl2::{pixels::Color, rect::Rect, render::Canvas, video::Window};
+
+use super::font::{self, CHAR_WIDTH, LINE_HEIGHT};
+use super::io::Action;
+
+// How long a message stays up
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+// Older messages are dropped once there are more than this
+const MAX_MESSAGES: usize = 4;
+// How often the FPS counter updates
+const FPS_INTERVAL: Duration = Duration::from_millis(500);
+// Frames a second from the Game Boy, 4194304 cycles over 70224 a frame
+const GAME_BOY_FPS: f32 = 59.7275;
+
+const TEXT: Color = Color::RGB(240, 240, 240);
+const DIM: Color = Color::RGB(150, 150, 150);
+const BACKDROP: Color = Color::RGBA(0, 0, 0, 170);
+const SELECTED: Color = Color::RGB(255, 220, 90);
+
+/// Entries in the pause menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuItem {
+    Resume,
+    Reset,
+    Action(Action),
+    Quit,
+}
+
+pub const MENU: [(&str, MenuItem); 9] = [
+    ("Resume", MenuItem::Resume),
+    ("Load ROM", MenuItem::Action(Action::LoadRom)),
+    ("Reset", MenuItem::Reset),
+    ("Next palette", MenuItem::Action(Action::NextPalette)),
+    ("Next filter", MenuItem::Action(Action::NextFilter)),
+    ("Fullscreen", MenuItem::Action(Action::Fullscreen)),
+    ("Show FPS", MenuItem::Action(Action::ShowFps)),
+    ("Bind keys", MenuItem::Action(Action::BindKeys)),
+    ("Quit", MenuItem::Quit),
+];
+
+pub struct Osd {
+    messages: VecDeque<(String, Instant)>,
+    // Stays up until cleared, for things waiting on the user
+    prompt: Option<String>,
+    pub show_fps: bool,
+    // Frames drawn and emulated since the counter last updated
+    drawn: u32,
+    emulated: u32,
+    counted_since: Instant,
+    fps: f32,
+    speed: f32,
+    /// Highlighted pause menu entry
+    pub selected: usize,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+            prompt: None,
+            show_fps: false,
+            drawn: 0,
+            emulated: 0,
+            counted_since: Instant::now(),
+            fps: 0.0,
+            speed: 0.0,
+            selected: 0,
+        }
+    }
+
+    /// Shows a message for a few seconds, and logs it to stdout
+    pub fn message(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        println!("{text}");
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((text, Instant::now()));
+    }
+
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        if let Some(prompt) = &prompt {
+            println!("{prompt}");
+        }
+        self.prompt = prompt;
+    }
+
+    /// Counts a drawn frame and how many Game Boy frames were run for it
+    pub fn count_frame(&mut self, emulated: u32) {
+        self.drawn += 1;
+        self.emulated += emulated;
+        let elapsed = self.counted_since.elapsed();
+        if elapsed >= FPS_INTERVAL {
+            let seconds = elapsed.as_secs_f32();
+            self.fps = self.drawn as f32 / seconds;
+            self.speed = self.emulated as f32 / seconds / GAME_BOY_FPS * 100.0;
+            self.drawn = 0;
+            self.emulated = 0;
+            self.counted_since = Instant::now();
+        }
+    }
+
+    pub fn move_selection(&mut self, down: bool) {
+        self.selected = if down {
+            (self.selected + 1) % MENU.len()
+        } else {
+            self.selected.checked_sub(1).unwrap_or(MENU.len() - 1)
+        };
+    }
+
+    /// Draws over the game, which fills `game` in the window
+    pub fn draw(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        game: Rect,
+        paused: bool,
+    ) -> Result<(), String> {
+        let scale = (game.height() / 240).max(1);
+        let line = (LINE_HEIGHT * scale) as i32;
+        let margin = (CHAR_WIDTH * scale) as i32;
+
+        if paused {
+            self.draw_menu(canvas, game, scale)?;
+        }
+
+        if self.show_fps {
+            let text = format!("{:.0} FPS {:.0}%", self.fps, self.speed);
+            let x = game.right() - margin - font::text_width(&text, scale) as i32;
+            draw_label(canvas, x, game.y() + margin, scale, TEXT, &text)?;
+        }
+
+        self.messages
+            .retain(|(_, shown)| shown.elapsed() < MESSAGE_TIME);
+        let lines = self
+            .prompt
+            .iter()
+            .chain(self.messages.iter().rev().map(|(text, _)| text));
+        let mut y = game.bottom() - margin - line;
+        for text in lines {
+            draw_label(canvas, game.x() + margin, y, scale, TEXT, text)?;
+            y -= line + scale as i32;
+        }
+        Ok(())
+    }
+
+    fn draw_menu(&self, canvas: &mut Canvas<Window>, game: Rect, scale: u32) -> Result<(), String> {
+        canvas.set_draw_color(BACKDROP);
+        canvas.fill_rect(game)?;
+
+        // As big as fits, with a line spare above and below
+        let scale = (game.height() / (LINE_HEIGHT * (MENU.len() as u32 + 4))).max(scale);
+        let line = (LINE_HEIGHT * scale) as i32;
+        let height = line * (MENU.len() as i32 + 2);
+        let mut y = game.center().y() - height / 2;
+
+        let centered = |text: &str| game.center().x() - font::text_width(text, scale) as i32 / 2;
+        font::draw_text(canvas, centered("Paused"), y, scale, DIM, "Paused")?;
+        y += line * 2;
+        for (index, (name, _)) in MENU.iter().enumerate() {
+            let color = if index == self.selected {
+                SELECTED
+            } else {
+                TEXT
+            };
+            font::draw_text(canvas, centered(name), y, scale, color, name)?;
+            y += line;
+        }
+        Ok(())
+    }
+}
+
+// Text with a dark box behind it so it shows up over any game
+fn draw_label(
+    canvas: &mut Canvas<Window>,
+    x: i32,
+    y: i32,
+    scale: u32,
+    color: Color,
+    text: &str,
+) -> Result<(), String> {
+    let padding = scale as i32;
+    canvas.set_draw_color(BACKDROP);
+    canvas.fill_rect(Rect::new(
+        x - padding,
+        y - padding,
+        font::text_width(text, scale) + 2 * scale,
+        LINE_HEIGHT * scale + scale,
+    ))?;
+    font::draw_text(canvas, x, y, scale, color, text)
+}
@@ -0,0 +1,17 @@
+//! Where the frontend keeps its files
+
+use std::{env, path::PathBuf};
+
+/// The rbgb folder in the user's config dir, following each platform's convention
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    dir.map(|dir| dir.join("rbgb"))
+}
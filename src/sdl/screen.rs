@@ -1,63 +1,147 @@
 use std::{
-    io::{self, Write},
+    env,
+    path::{Path, PathBuf},
     thread,
     time::{Duration, Instant},
 };
 
-use crate::{
-    emulator::Emulator,
-    types::{SCREEN_HEIGHT, SCREEN_WIDTH},
+use rbgb::{
+    Emulator, GameInput, Movie, Palette, Paper, Recorder, SCREEN_HEIGHT, SCREEN_WIDTH, VideoFormat,
+    encode_png, timestamped_name,
 };
 use sdl2::{
     event::Event,
-    keyboard::Keycode,
+    keyboard::{Keycode, Mod},
     pixels::{Color, PixelFormatEnum},
     rect::Rect,
-    render::Texture,
+    render::{BlendMode, Texture},
+    video::FullscreenType,
 };
 
-use super::io::handle_joystick_input;
+use super::bindings::{BINDINGS, Bindings};
+use super::browser::{self, Browser, Outcome};
+use super::controller::Controllers;
+use super::filter::Filters;
+use super::io::{Action, Binding, handle_joystick_input};
+use super::osd::{MENU, MenuItem, Osd};
+use super::window::Geometry;
+use crate::cli::Options;
 
-// Window size multiplier so original 160x144 framebuffer is easier to see
-const WINDOW_SCALE: u32 = 5;
+// Window size multiplier the first time, so original 160x144 framebuffer is easier to see
+const DEFAULT_SCALE: u32 = 5;
+
+// Frames run for every frame shown while fast forwarding
+const FAST_FORWARD_SPEED: u32 = 4;
 
 pub struct SdlApp {
     _sdl_context: sdl2::Sdl,
     event_pump: sdl2::EventPump,
     canvas: sdl2::render::Canvas<sdl2::video::Window>,
+    // Saved when the emulator closes, and kept from before going fullscreen
+    geometry: Geometry,
+    // Fullscreen only for this run because of --fullscreen, so it isn't saved
+    fullscreen_from_cli: bool,
+    // How fast the emulator runs compared to real time
+    speed: f32,
+    // Index into the palette presets, one past the end picks the CGB colors for the game
+    palette: usize,
+    // Cheats are kept next to the ROM, and movies too unless there's a save dir
+    rom_path: Option<PathBuf>,
+    save_dir: Option<PathBuf>,
+    controllers: Controllers,
+    bindings: Bindings,
+    // Index into BINDINGS of the one waiting for a key press
+    binding: Option<usize>,
+    // ROM picker, the game waits while it's open
+    browser: Option<Browser>,
+    recent: Vec<PathBuf>,
+    filters: Filters,
+    // Set when the filter changes so the current frame is shown with it
+    refilter: bool,
+    osd: Osd,
+    fast_forward: bool,
+    // Video being recorded, and the format the next one starts in
+    recorder: Option<Recorder>,
+    video_format: VideoFormat,
+    // The page coming out of the printer, and how many have been saved
+    paper: Paper,
+    pages: u32,
 }
 
 impl SdlApp {
-    pub fn new() -> Result<Self, String> {
+    pub fn new(options: &Options) -> Result<Self, String> {
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
-        let window = video_subsystem
-            .window(
-                "Gameboy Emulator",
-                SCREEN_WIDTH * WINDOW_SCALE,
-                SCREEN_HEIGHT * WINDOW_SCALE,
-            )
-            .position_centered()
-            .build()
+        // The command line wins over however the window was left last time
+        let mut geometry = Geometry::load(DEFAULT_SCALE);
+        if let Some(scale) = options.scale {
+            geometry.width = SCREEN_WIDTH * scale;
+            geometry.height = SCREEN_HEIGHT * scale;
+        }
+        let fullscreen_from_cli = options.fullscreen && !geometry.fullscreen;
+        geometry.fullscreen |= options.fullscreen;
+
+        let mut window =
+            video_subsystem.window("Gameboy Emulator", geometry.width, geometry.height);
+        window.resizable();
+        match geometry.position {
+            Some((x, y)) => window.position(x, y),
+            None => window.position_centered(),
+        };
+        if geometry.fullscreen {
+            window.fullscreen_desktop();
+        }
+        let mut window = window.build().map_err(|e| e.to_string())?;
+        window
+            .set_minimum_size(SCREEN_WIDTH, SCREEN_HEIGHT)
             .map_err(|e| e.to_string())?;
 
-        let canvas = window
+        let mut canvas = window
             .into_canvas()
             .accelerated()
             .build()
             .map_err(|e| e.to_string())?;
+        // Lets the on-screen display darken what's behind it
+        canvas.set_blend_mode(BlendMode::Blend);
 
         let event_pump = sdl_context.event_pump()?;
+        // Connected controllers show up as added events once the pump starts
+        let controllers = Controllers::new(sdl_context.game_controller()?);
+        // Carry on cycling from the palette picked on the command line
+        let palette = options
+            .palette
+            .and_then(|palette| Palette::PRESETS.iter().position(|(_, p)| *p == palette))
+            .unwrap_or_default();
 
         Ok(Self {
             _sdl_context: sdl_context,
             event_pump,
             canvas,
+            geometry,
+            fullscreen_from_cli,
+            speed: options.speed,
+            palette,
+            rom_path: None,
+            save_dir: options.save_dir.clone(),
+            controllers,
+            bindings: Bindings::load(),
+            binding: None,
+            browser: None,
+            recent: browser::load_recent(),
+            filters: Filters::default(),
+            refilter: false,
+            osd: Osd::new(),
+            fast_forward: false,
+            recorder: None,
+            video_format: options.video_format,
+            paper: Paper::new(),
+            pages: 0,
         })
     }
 
     pub fn run(&mut self, emulator: &mut Emulator) -> Result<(), String> {
-        // Create the streaming texture once per run so we can push raw RGB data to it
+        // Create the streaming texture once per run so we can push raw RGB data to it,
+        // and again whenever the filter changes the size
         let texture_creator = self.canvas.texture_creator();
         let mut texture = texture_creator
             .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH, SCREEN_HEIGHT)
@@ -68,106 +152,536 @@ impl SdlApp {
 
             // Process all queued SDL events before running a frame
             while let Some(event) = self.event_pump.poll_event() {
-                if !Self::handle_event(event, emulator) {
+                if !self.handle_event(event, emulator) {
                     break 'running;
                 }
             }
 
-            // Advance the emulator state, copy the LCD buffer into SDL, then render
-            emulator.update();
-            Self::blit_rgb_bytes_to_texture(emulator, &mut texture)?;
+            // Advance the emulator state, copy a new frame into SDL, then render
+            let frames = if self.fast_forward {
+                FAST_FORWARD_SPEED
+            } else {
+                1
+            };
+            let mut new_frames = 0;
+            for _ in 0..frames {
+                if self.browser.is_some() {
+                    break;
+                }
+                if emulator.update() {
+                    new_frames += 1;
+                    self.record_frame(emulator);
+                    self.collect_print(emulator);
+                }
+            }
+            self.osd.count_frame(new_frames);
+            if new_frames > 0 || self.refilter {
+                self.refilter = false;
+                let (data, width, height) = self.filters.apply(emulator.get_display_buffer());
+                let query = texture.query();
+                if (query.width, query.height) != (width, height) {
+                    texture = texture_creator
+                        .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+                        .map_err(|e| e.to_string())?;
+                }
+                Self::blit_rgb_bytes_to_texture(data, width, height, &mut texture)?;
+            }
 
             self.draw(emulator.is_paused(), &texture)?;
             self.limit_frame_rate(frame_start);
         }
 
+        self.stop_video_recording();
+        self.save_page();
+        self.remember_geometry();
+        if self.fullscreen_from_cli {
+            self.geometry.fullscreen = false;
+        }
+        self.geometry.save();
         Ok(())
     }
 
+    // Keeps the windowed size and position, which fullscreen would replace
+    fn remember_geometry(&mut self) {
+        if self.geometry.fullscreen {
+            return;
+        }
+        let window = self.canvas.window();
+        (self.geometry.width, self.geometry.height) = window.size();
+        self.geometry.position = Some(window.position());
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        self.remember_geometry();
+        self.geometry.fullscreen = !self.geometry.fullscreen;
+        // Whatever is picked in the window is kept for next time
+        self.fullscreen_from_cli = false;
+        let fullscreen = if self.geometry.fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        if let Err(e) = self.canvas.window_mut().set_fullscreen(fullscreen) {
+            self.osd
+                .message(format!("Failed to change fullscreen: {e}"));
+        }
+    }
+
     // Returns false when the emulator should stop running (e.g. window closed)
-    fn handle_event(event: Event, emulator: &mut Emulator) -> bool {
+    fn handle_event(&mut self, event: Event, emulator: &mut Emulator) -> bool {
         match event {
-            Event::Quit { .. } => false,
+            Event::Quit { .. } => return false,
+            // Alt+Enter is the usual fullscreen shortcut, whatever the bindings say
             Event::KeyDown {
-                keycode: Some(Keycode::P),
+                keycode: Some(Keycode::Return),
+                keymod,
+                repeat: false,
                 ..
-            } => {
-                emulator.toggle_pause();
-                true
+            } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                self.toggle_fullscreen();
+                return true;
+            }
+            Event::DropFile { filename, .. } => {
+                if self.load_rom(emulator, Path::new(&filename)) {
+                    self.browser = None;
+                }
+                return true;
             }
+            _ => {}
+        }
+        // The browser takes key presses and controller buttons, while hot-plugging
+        // and releasing held keys and buttons carries on as usual
+        if let Some(browser) = &mut self.browser
+            && let Event::KeyDown { .. } | Event::ControllerButtonDown { .. } = event
+        {
+            let outcome = menu_key(&event, &self.bindings)
+                .and_then(|key| browser.handle_key(key, &self.recent));
+            let close = match outcome {
+                Some(Outcome::Open(path)) => self.load_rom(emulator, &path),
+                Some(Outcome::Close) => true,
+                None => false,
+            };
+            if close {
+                self.browser = None;
+            }
+            return true;
+        }
+        // Only fresh key presses are taken for bindings, controllers still get
+        // connected and held keys still get released
+        if let Some(index) = self.binding
+            && let Event::KeyDown {
+                keycode: Some(key),
+                repeat: false,
+                ..
+            } = event
+        {
+            self.bind_next(index, key);
+            return true;
+        }
+        if emulator.is_paused()
+            && let Some(key) = menu_key(&event, &self.bindings)
+            && let Some(running) = self.pause_menu(key, emulator)
+        {
+            return running;
+        }
+        if let Some((action, pressed)) =
+            self.controllers
+                .handle_event(&event, &self.bindings, emulator)
+        {
+            self.perform(action, pressed, emulator);
+            return true;
+        }
+
+        let key_action = |key| match self.bindings.key(key) {
+            Some(Binding::Action(action)) => Some(action),
+            _ => None,
+        };
+        let action = match event {
             Event::KeyDown {
-                keycode: Some(Keycode::L),
+                keycode: Some(key),
+                repeat,
                 ..
-            } => {
-                print!("Enter path to ROM: ");
-                if io::stdout().flush().is_ok() {
-                    let mut path = String::new();
-                    if io::stdin().read_line(&mut path).is_err() {
-                        println!("Failed to read ROM path from stdin");
-                    } else {
-                        let trimmed = path.trim();
-                        if trimmed.is_empty() {
-                            println!("No ROM path entered");
-                        } else if let Err(e) = emulator.load_rom(trimmed) {
-                            println!("Failed to load ROM: {e}");
-                        } else {
-                            println!("ROM loaded");
-                        }
-                    }
+            } => key_action(key).map(|action| (action, true, repeat)),
+            Event::KeyUp {
+                keycode: Some(key), ..
+            } => key_action(key).map(|action| (action, false, false)),
+            _ => None,
+        };
+
+        match action {
+            // Holding a key down shouldn't keep toggling things
+            Some((action, pressed, repeat)) => {
+                if !repeat {
+                    self.perform(action, pressed, emulator);
                 }
-                true
             }
+            // handle all remaining inputs as a game input
+            None => handle_joystick_input(event, &self.bindings, emulator),
+        }
+        true
+    }
+
+    // Moves around the pause menu, returning None for keys it doesn't use and
+    // otherwise whether the emulator should keep running
+    fn pause_menu(&mut self, key: Keycode, emulator: &mut Emulator) -> Option<bool> {
+        match key {
+            Keycode::Up => self.osd.move_selection(false),
+            Keycode::Down => self.osd.move_selection(true),
+            Keycode::Escape => emulator.toggle_pause(),
+            Keycode::Return | Keycode::KpEnter => match MENU[self.osd.selected].1 {
+                MenuItem::Resume => emulator.toggle_pause(),
+                MenuItem::Reset => {
+                    emulator.reset();
+                    self.osd.message("Reset");
+                }
+                MenuItem::Action(action) => self.perform(action, true, emulator),
+                MenuItem::Quit => return Some(false),
+            },
+            _ => return None,
+        }
+        Some(true)
+    }
+
+    fn perform(&mut self, action: Action, pressed: bool, emulator: &mut Emulator) {
+        // Only fast forward cares about the button coming back up
+        if action == Action::FastForward {
+            if pressed != self.fast_forward {
+                let frames = if pressed { FAST_FORWARD_SPEED } else { 1 };
+                self.osd
+                    .message(format!("Speed {}x", self.speed * frames as f32));
+            }
+            self.fast_forward = pressed;
+            return;
+        }
+        if !pressed {
+            return;
+        }
+
+        match action {
+            Action::Pause => {
+                emulator.toggle_pause();
+                self.osd.selected = 0;
+            }
+            Action::ShowFps => self.osd.show_fps = !self.osd.show_fps,
+            Action::Screenshot => self.screenshot(emulator, false),
+            Action::ScreenshotFiltered => self.screenshot(emulator, true),
+            Action::LoadRom => self.open_browser(),
+            Action::NextPalette => self.next_palette(emulator),
             // Dump the lcd memory details
-            Event::KeyDown {
-                keycode: Some(Keycode::O),
-                ..
-            } => {
-                emulator.dump_lcd_mem();
-                true
+            Action::DumpLcd => emulator.dump_lcd_mem(),
+            Action::Record => self.toggle_recording(emulator),
+            Action::PlayMovie => self.play_movie(emulator),
+            Action::Printer => {
+                let connected = !emulator.printer_connected();
+                emulator.connect_printer(connected);
+                if connected {
+                    self.osd.message("Printer plugged in");
+                } else {
+                    self.save_page();
+                    self.osd.message("Printer unplugged");
+                }
             }
+            Action::RecordVideo => match self.recorder {
+                Some(_) => self.stop_video_recording(),
+                None => {
+                    let path = self.capture_path("", self.video_format.extension());
+                    self.start_video_recording(&path, self.video_format);
+                }
+            },
+            Action::Fullscreen => self.toggle_fullscreen(),
+            Action::NextFilter => {
+                self.filters.filter = self.filters.filter.next();
+                self.refilter = true;
+                self.osd
+                    .message(format!("Filter: {}", self.filters.filter.name()));
+            }
+            Action::FrameBlend => {
+                self.filters.blend = !self.filters.blend;
+                self.refilter = true;
+                let blend = if self.filters.blend { "on" } else { "off" };
+                self.osd.message(format!("Frame blending: {blend}"));
+            }
+            Action::ScaleMode => {
+                self.geometry.mode = self.geometry.mode.next();
+                self.osd
+                    .message(format!("Scaling: {}", self.geometry.mode.name()));
+            }
+            Action::BindKeys => self.prompt_for_binding(0),
+            Action::FastForward => {}
+        }
+    }
 
-            // handle all remaining inputs as a game input
-            event => {
-                handle_joystick_input(event, emulator);
-                true
+    fn prompt_for_binding(&mut self, index: usize) {
+        match BINDINGS.get(index) {
+            Some((name, binding)) => {
+                let current = self.bindings.key_names(*binding);
+                self.osd.set_prompt(Some(format!(
+                    "Press a key for {name} (now {current}), Esc keeps it"
+                )));
+                self.binding = Some(index);
+            }
+            None => {
+                self.binding = None;
+                self.osd.set_prompt(None);
+                self.bindings.save();
+                self.osd.message("Key bindings saved");
+            }
+        }
+    }
+
+    fn bind_next(&mut self, index: usize, key: Keycode) {
+        if key != Keycode::Escape {
+            self.bindings.bind_key(key, BINDINGS[index].1);
+        }
+        self.prompt_for_binding(index + 1);
+    }
+
+    // Starts in the folder of the last ROM, or wherever the emulator was run from
+    fn open_browser(&mut self) {
+        let dir = self
+            .rom_path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .filter(|dir| dir.is_dir())
+            .map(Path::to_path_buf)
+            .or_else(|| env::current_dir().ok())
+            .unwrap_or_default();
+        self.browser = Some(Browser::new(dir, &self.recent));
+    }
+
+    /// Loads a ROM along with its cheats, returning whether it loaded
+    pub fn load_rom(&mut self, emulator: &mut Emulator, path: &Path) -> bool {
+        let path_str = path.to_string_lossy();
+        if let Err(e) = emulator.load_rom(&path_str) {
+            self.osd.message(format!("Failed to load ROM: {e}"));
+            return false;
+        }
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        self.osd
+            .message(format!("Loaded {}", name.to_string_lossy()));
+        self.load_cheats(emulator, &path_str);
+        browser::add_recent(&mut self.recent, path);
+        self.rom_path = Some(path.to_path_buf());
+        true
+    }
+
+    // Cheats for a ROM live next to it with a .cht extension
+    fn load_cheats(&mut self, emulator: &mut Emulator, rom_path: &str) {
+        emulator.cheats_mut().clear();
+        let path = Path::new(rom_path).with_extension("cht");
+        if !path.exists() {
+            return;
+        }
+        match emulator.load_cheats(&path.to_string_lossy()) {
+            Ok(count) => self.osd.message(format!("Loaded {count} cheats")),
+            Err(e) => self.osd.message(format!("Failed to load cheats: {e}")),
+        }
+    }
+
+    // Files without a ROM to sit next to go in the save dir, or failing that
+    // the ROM's folder, or wherever the emulator was run from
+    fn output_path(&self, name: &str) -> PathBuf {
+        let dir = self
+            .save_dir
+            .as_deref()
+            .or_else(|| self.rom_path.as_deref().and_then(Path::parent));
+        match dir {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+
+    // Screenshots, videos and prints are named after the ROM and the time
+    fn capture_path(&self, label: &str, extension: &str) -> PathBuf {
+        let stem = self
+            .rom_path
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map_or("rbgb".into(), |stem| stem.to_string_lossy());
+        self.output_path(&timestamped_name(&format!("{stem}{label}"), extension))
+    }
+
+    // Filtered screenshots are blown up to about the size the game is shown at
+    fn screenshot(&mut self, emulator: &Emulator, filtered: bool) {
+        let path = self.capture_path("", "png");
+        let png = if filtered {
+            let (data, width, height) = self.filters.last();
+            if width == 0 || height == 0 {
+                self.osd
+                    .message("Nothing drawn to take a screenshot of yet");
+                return;
+            }
+            let shown = match self.canvas.output_size() {
+                Ok((w, h)) => self.geometry.mode.game_rect(w, h).height(),
+                Err(_) => height,
+            };
+            let scale = (shown / height).max(1);
+            let scaled = scale_up(data, width, height, scale);
+            encode_png(width * scale, height * scale, &scaled)
+        } else {
+            emulator.screenshot_png()
+        };
+        match std::fs::write(&path, png) {
+            Ok(()) => self
+                .osd
+                .message(format!("Screenshot saved to {}", path.display())),
+            Err(e) => self.osd.message(format!("Failed to save screenshot: {e}")),
+        }
+    }
+
+    /// Starts recording every frame the emulator runs to a video file
+    pub fn start_video_recording(&mut self, path: &Path, format: VideoFormat) {
+        match Recorder::create(&path.to_string_lossy(), format) {
+            Ok(recorder) => {
+                self.recorder = Some(recorder);
+                let keys = self
+                    .bindings
+                    .key_names(Binding::Action(Action::RecordVideo));
+                self.osd.message(format!(
+                    "Recording video to {}, {keys} stops",
+                    path.display()
+                ));
+            }
+            Err(e) => self.osd.message(format!("Failed to start video: {e}")),
+        }
+    }
+
+    fn stop_video_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(frames) => self.osd.message(format!("Saved {frames} frames of video")),
+                Err(e) => self.osd.message(format!("Failed to save video: {e}")),
+            }
+        }
+    }
+
+    // A failed write ends the recording rather than failing every frame after
+    fn record_frame(&mut self, emulator: &Emulator) {
+        if let Some(recorder) = &mut self.recorder
+            && let Err(e) = emulator.record_frame(recorder)
+        {
+            self.recorder = None;
+            self.osd.message(format!("Stopped recording video: {e}"));
+        }
+    }
+
+    // Prints go on the paper, and the page is saved once the paper is fed out
+    fn collect_print(&mut self, emulator: &mut Emulator) {
+        if let Some(print) = emulator.take_print()
+            && self.paper.add(&print)
+        {
+            self.save_page();
+        }
+    }
+
+    fn save_page(&mut self) {
+        if !self.paper.is_empty() {
+            self.pages += 1;
+            let path = self.capture_path(&format!("-print{}", self.pages), "png");
+            match std::fs::write(&path, self.paper.to_png()) {
+                Ok(()) => self
+                    .osd
+                    .message(format!("Print saved to {}", path.display())),
+                Err(e) => self.osd.message(format!("Failed to save print: {e}")),
+            }
+        }
+        self.paper.clear();
+    }
+
+    fn movie_path(&self) -> Option<PathBuf> {
+        let path = self.rom_path.as_ref()?.with_extension("rbm");
+        match (&self.save_dir, path.file_name()) {
+            (Some(dir), Some(name)) => Some(dir.join(name)),
+            _ => Some(path),
+        }
+    }
+
+    fn toggle_recording(&mut self, emulator: &mut Emulator) {
+        let Some(path) = self.movie_path() else {
+            self.osd.message("Load a ROM before recording a movie");
+            return;
+        };
+        match emulator.stop_recording() {
+            Some(movie) => match movie.save(&path.to_string_lossy()) {
+                Ok(()) => self.osd.message(format!(
+                    "Saved {} frames to {}",
+                    movie.frames.len(),
+                    path.display()
+                )),
+                Err(e) => self.osd.message(format!("Failed to save movie: {e}")),
+            },
+            None => {
+                emulator.start_recording();
+                let keys = self.bindings.key_names(Binding::Action(Action::Record));
+                self.osd
+                    .message(format!("Recording from power on, {keys} again stops"));
+            }
+        }
+    }
+
+    fn play_movie(&mut self, emulator: &mut Emulator) {
+        let Some(path) = self.movie_path() else {
+            self.osd.message("Load a ROM before playing a movie");
+            return;
+        };
+        if emulator.is_playing_movie() {
+            emulator.stop_movie();
+            self.osd.message("Movie stopped");
+            return;
+        }
+        let result = Movie::load(&path.to_string_lossy())
+            .and_then(|movie| emulator.play_movie(movie).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => self.osd.message(format!("Playing {}", path.display())),
+            Err(e) => self.osd.message(format!("Failed to play movie: {e}")),
+        }
+    }
+
+    fn next_palette(&mut self, emulator: &mut Emulator) {
+        self.palette = (self.palette + 1) % (Palette::PRESETS.len() + 1);
+        match Palette::PRESETS.get(self.palette) {
+            Some((name, palette)) => {
+                emulator.set_palette(*palette);
+                self.osd.message(format!("Palette: {name}"));
+            }
+            None => {
+                emulator.set_palette(emulator.cgb_compat_palette());
+                self.osd.message("Palette: CGB colors for this game");
             }
         }
     }
 
     fn draw(&mut self, paused: bool, texture: &sdl2::render::Texture) -> Result<(), String> {
-        self.canvas.clear();
-        self.canvas.copy(
-            texture,
-            None,
-            Some(Rect::new(
-                0,
-                0,
-                SCREEN_WIDTH * WINDOW_SCALE,
-                SCREEN_HEIGHT * WINDOW_SCALE,
-            )),
-        )?;
-
-        if paused {
-            self.canvas.set_draw_color(Color::RGB(50, 50, 50));
-            self.canvas.fill_rect(Rect::new(
-                0,
-                0,
-                SCREEN_WIDTH * WINDOW_SCALE,
-                SCREEN_HEIGHT * WINDOW_SCALE,
-            ))?;
+        if let Some(browser) = &mut self.browser {
+            browser.draw(&mut self.canvas)?;
+            let (width, height) = self.canvas.output_size()?;
+            self.osd
+                .draw(&mut self.canvas, Rect::new(0, 0, width, height), false)?;
+            self.canvas.present();
+            return Ok(());
         }
 
+        // Whatever the game doesn't cover is left black
+        let (width, height) = self.canvas.output_size()?;
+        let game = self.geometry.mode.game_rect(width, height);
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.copy(texture, None, Some(game))?;
+
+        self.osd.draw(&mut self.canvas, game, paused)?;
+
         self.canvas.present();
         Ok(())
     }
 
     fn blit_rgb_bytes_to_texture(
-        emulator: &Emulator,
+        data: &[u8],
+        width: u32,
+        height: u32,
         texture: &mut Texture,
     ) -> Result<(), String> {
-        let data = emulator.get_display_buffer();
-        let pitch = (SCREEN_WIDTH * 3) as usize; // 3 bytes per pixel
-        let expected_len = pitch * SCREEN_HEIGHT as usize;
+        let pitch = (width * 3) as usize; // 3 bytes per pixel
+        let expected_len = pitch * height as usize;
 
         if data.len() != expected_len {
             return Err(format!(
@@ -183,11 +697,48 @@ impl SdlApp {
         Ok(())
     }
 
-    // Simple 60 FPS limiter so SDL doesn't run as fast as possible
+    // Simple 60 FPS limiter so SDL doesn't run as fast as possible, scaled by the speed
     fn limit_frame_rate(&self, frame_start: Instant) {
         let frame_duration = frame_start.elapsed();
-        if frame_duration < Duration::from_millis(16) {
-            thread::sleep(Duration::from_millis(16) - frame_duration);
+        let target = Duration::from_millis(16).div_f32(self.speed);
+        if frame_duration < target {
+            thread::sleep(target - frame_duration);
+        }
+    }
+}
+
+// The key a menu treats a key press or controller button as, with the Game
+// Boy's D-pad moving around, A choosing and B backing out
+fn menu_key(event: &Event, bindings: &Bindings) -> Option<Keycode> {
+    match *event {
+        Event::KeyDown { keycode, .. } => keycode,
+        Event::ControllerButtonDown { button, .. } => match bindings.button(button)? {
+            Binding::Game(GameInput::Up) => Some(Keycode::Up),
+            Binding::Game(GameInput::Down) => Some(Keycode::Down),
+            Binding::Game(GameInput::Left) => Some(Keycode::Left),
+            Binding::Game(GameInput::Right) => Some(Keycode::Right),
+            Binding::Game(GameInput::A | GameInput::Start) => Some(Keycode::Return),
+            Binding::Game(GameInput::B) => Some(Keycode::Escape),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Nearest neighbour upscale of RGB888 pixels
+fn scale_up(rgb: &[u8], width: u32, height: u32, scale: u32) -> Vec<u8> {
+    let (width, scale) = (width as usize, scale as usize);
+    let mut out = Vec::with_capacity(rgb.len() * scale * scale);
+    for row in rgb.chunks_exact(width * 3).take(height as usize) {
+        let start = out.len();
+        for pixel in row.chunks_exact(3) {
+            for _ in 0..scale {
+                out.extend_from_slice(pixel);
+            }
+        }
+        for _ in 1..scale {
+            out.extend_from_within(start..start + width * 3 * scale);
         }
     }
+    out
 }
@@ -0,0 +1,179 @@
+//! Window size, placement and how the game is scaled to fit it
+
+use std::{fs, path::PathBuf};
+
+use rbgb::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use sdl2::rect::Rect;
+
+use super::paths::config_dir;
+
+/// How the 160x144 picture is stretched over the window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Whole multiples only, so every Game Boy pixel is the same size
+    #[default]
+    Integer,
+    /// As large as fits while keeping the aspect ratio
+    Fit,
+}
+
+impl ScaleMode {
+    pub fn next(self) -> Self {
+        match self {
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Integer,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Fit => "fit",
+        }
+    }
+
+    /// Where the game goes in a window of the given size, centered with black
+    /// bars on the sides left over
+    pub fn game_rect(self, width: u32, height: u32) -> Rect {
+        let (w, h) = match self {
+            ScaleMode::Integer => {
+                let scale = (width / SCREEN_WIDTH).min(height / SCREEN_HEIGHT).max(1);
+                (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale)
+            }
+            // Compare the aspect ratios without rounding to see which side is full
+            ScaleMode::Fit => {
+                if width * SCREEN_HEIGHT > height * SCREEN_WIDTH {
+                    (height * SCREEN_WIDTH / SCREEN_HEIGHT, height)
+                } else {
+                    (width, width * SCREEN_HEIGHT / SCREEN_WIDTH)
+                }
+            }
+        };
+        let x = (width as i32 - w as i32) / 2;
+        let y = (height as i32 - h as i32) / 2;
+        Rect::new(x, y, w.max(1), h.max(1))
+    }
+}
+
+/// The window as it was left last time
+pub struct Geometry {
+    // Unset the first time, so SDL centers the window
+    pub position: Option<(i32, i32)>,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    pub mode: ScaleMode,
+}
+
+impl Geometry {
+    pub fn new(scale: u32) -> Self {
+        Self {
+            position: None,
+            width: SCREEN_WIDTH * scale,
+            height: SCREEN_HEIGHT * scale,
+            fullscreen: false,
+            mode: ScaleMode::default(),
+        }
+    }
+
+    /// Reads the saved geometry, falling back to a window of the given scale
+    /// for anything missing
+    pub fn load(scale: u32) -> Self {
+        let mut geometry = Self::new(scale);
+        let Some(text) = geometry_path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return geometry;
+        };
+
+        let (mut x, mut y) = (None, None);
+        for (name, value) in text
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(name, value)| (name.trim(), value.trim()))
+        {
+            match name {
+                "x" => x = value.parse().ok(),
+                "y" => y = value.parse().ok(),
+                "width" => geometry.width = value.parse().unwrap_or(geometry.width),
+                "height" => geometry.height = value.parse().unwrap_or(geometry.height),
+                "fullscreen" => geometry.fullscreen = value == "true",
+                "mode" if value == ScaleMode::Fit.name() => geometry.mode = ScaleMode::Fit,
+                _ => {}
+            }
+        }
+        geometry.position = x.zip(y);
+        // Don't come back as a window too small to use
+        geometry.width = geometry.width.max(SCREEN_WIDTH);
+        geometry.height = geometry.height.max(SCREEN_HEIGHT);
+        geometry
+    }
+
+    pub fn save(&self) {
+        let Some(path) = geometry_path() else {
+            return;
+        };
+        let mut text = String::new();
+        if let Some((x, y)) = self.position {
+            text.push_str(&format!("x = {x}\ny = {y}\n"));
+        }
+        text.push_str(&format!(
+            "width = {}\nheight = {}\nfullscreen = {}\nmode = {}\n",
+            self.width,
+            self.height,
+            self.fullscreen,
+            self.mode.name()
+        ));
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, text));
+        if let Err(e) = result {
+            println!("Failed to save window geometry: {e}");
+        }
+    }
+}
+
+fn geometry_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("window.ini"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ntest::timeout;
+
+    #[test]
+    #[timeout(10)]
+    fn test_game_rect_letterboxes() {
+        // An exact multiple fills the window either way
+        for mode in [ScaleMode::Integer, ScaleMode::Fit] {
+            assert_eq!(mode.game_rect(800, 720), Rect::new(0, 0, 800, 720));
+        }
+
+        // Bars on the sides of a wide window
+        assert_eq!(
+            ScaleMode::Integer.game_rect(1000, 720),
+            Rect::new(100, 0, 800, 720)
+        );
+        assert_eq!(
+            ScaleMode::Fit.game_rect(1000, 720),
+            Rect::new(100, 0, 800, 720)
+        );
+
+        // Integer stops at the last whole multiple, fit fills the width
+        assert_eq!(
+            ScaleMode::Integer.game_rect(1000, 1000),
+            Rect::new(20, 68, 960, 864)
+        );
+        assert_eq!(
+            ScaleMode::Fit.game_rect(1000, 1000),
+            Rect::new(0, 50, 1000, 900)
+        );
+
+        // Integer never goes below 1x, even if that overflows the window
+        assert_eq!(
+            ScaleMode::Integer.game_rect(100, 100),
+            Rect::new(-30, -22, 160, 144)
+        );
+        assert_eq!(ScaleMode::Fit.game_rect(100, 100), Rect::new(0, 5, 100, 90));
+    }
+}
@@ -2,7 +2,7 @@ pub type Byte = u8;
 pub type Word = u16;
 pub type Ram = [Byte; MEM_SIZE];
 #[allow(clippy::upper_case_acronyms)]
-pub type LCD = [Byte; (SCREEN_HEIGHT * SCREEN_WIDTH * 3) as usize];
+pub type LCD = [Byte; (SCREEN_HEIGHT * SCREEN_WIDTH * 4) as usize]; // Room for the widest pixel format
 
 // Timer and CPU constants
 pub const TIMA: Word = 0xFF05;
@@ -15,7 +15,9 @@ pub const IE: Word = 0xFFFF; // Interrupt enabled register
 pub const IF: Word = 0xFF0F; // Interrupt request register
 
 // Screen Constants
+/// Height of the LCD in pixels
 pub const SCREEN_HEIGHT: u32 = 144;
+/// Width of the LCD in pixels
 pub const SCREEN_WIDTH: u32 = 160;
 pub const CURRENT_SCANLINE: Word = 0xFF44;
 pub const LCD_STATUS: Word = 0xFF41;
@@ -29,22 +31,59 @@ pub const MODE_3_BOUNDS: i32 = MODE_2_BOUNDS - 172;
 // Input Constants
 pub const INPUT_REGISTER: Word = 0xFF00;
 
-#[derive(Debug)]
+// Boot ROM Constants
+pub const BOOT_REG: Word = 0xFF50; // Unmaps the boot ROM when written
+pub const BOOT_ROM_SIZE: usize = 0x100;
+pub const BOOT_ROM_SIZE_CGB: usize = 0x900;
+
+// CGB Constants
+pub const KEY0: Word = 0xFF4C; // CGB or DMG compatibility mode, set by the boot ROM
+pub const KEY1: Word = 0xFF4D; // Speed switch
+pub const VBK: Word = 0xFF4F; // VRAM bank
+pub const HDMA1: Word = 0xFF51; // VRAM DMA source high
+pub const HDMA2: Word = 0xFF52; // VRAM DMA source low
+pub const HDMA3: Word = 0xFF53; // VRAM DMA destination high
+pub const HDMA4: Word = 0xFF54; // VRAM DMA destination low
+pub const HDMA5: Word = 0xFF55; // VRAM DMA length/mode/start
+pub const BCPS: Word = 0xFF68; // Background palette index
+pub const BCPD: Word = 0xFF69; // Background palette data
+pub const OCPS: Word = 0xFF6A; // Sprite palette index
+pub const OCPD: Word = 0xFF6B; // Sprite palette data
+pub const SVBK: Word = 0xFF70; // WRAM bank
+
+// Serial Constants
+pub const SERIAL_DATA: Word = 0xFF01;
+pub const SERIAL_CONTROL: Word = 0xFF02;
+
+/// Buttons on the Game Boy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum GameInput {
+    /// D-pad up
     Up,
+    /// D-pad left
     Left,
+    /// D-pad right
     Right,
+    /// D-pad down
     Down,
+    /// Start button
     Start,
+    /// Select button
     Select,
+    /// A button
     A,
+    /// B button
     B,
+    /// Input that doesn't map to a button, ignored
     Unknown,
 }
 
+/// Whether a button is held down
 #[derive(Copy, Clone, Default)]
 pub enum KeyState {
+    /// The button is held down
     Pressed = 0,
+    /// The button is up
     #[default]
     Released = 1,
 }